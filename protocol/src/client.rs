@@ -0,0 +1,217 @@
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use crate::msg::{self, MessageId};
+use crate::msg::auth::Authenticator;
+use crate::msg::codec::{self, Message, Request};
+use crate::msg::ps::*;
+
+/// Station transport, TCP and unix sockets carry the same messages framed with a length prefix
+pub enum Transport {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// Connection to a station, frames are signed and verified when an authenticator is given
+pub struct Client {
+    transport: Transport,
+    auth: Option<Authenticator>,
+}
+
+impl Client {
+    pub fn new(transport: Transport, auth: Option<Authenticator>) -> Client {
+        Client { transport, auth }
+    }
+
+    /// UDP socket bound to an ephemeral port of the matching address family
+    pub fn connect_udp(addr: SocketAddr, timeout: Option<Duration>) -> io::Result<Client> {
+        let local: IpAddr = match addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        Client::connect_udp_from(SocketAddr::new(local, 0), addr, timeout)
+    }
+
+    /// UDP socket bound to the given local address, e.g. a port a firewall lets through
+    pub fn connect_udp_from(local: SocketAddr, addr: SocketAddr, timeout: Option<Duration>) -> io::Result<Client> {
+        let udp = UdpSocket::bind(local)?;
+        udp.connect(addr)?;
+        udp.set_read_timeout(timeout)?;
+        Ok(Client::new(Transport::Udp(udp), None))
+    }
+
+    /// The timeout applies to connecting and to each response
+    pub fn connect_tcp(addr: SocketAddr, timeout: Option<Duration>) -> io::Result<Client> {
+        let stream = match timeout {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout)?,
+            None => TcpStream::connect(addr)?,
+        };
+        stream.set_read_timeout(timeout)?;
+        Ok(Client::new(Transport::Tcp(stream), None))
+    }
+
+    pub fn connect_unix(path: &str) -> io::Result<Client> {
+        Ok(Client::new(Transport::Unix(UnixStream::connect(path)?), None))
+    }
+
+    pub fn with_auth(mut self, auth: Option<Authenticator>) -> Client {
+        self.auth = auth;
+        self
+    }
+
+    /// Underlying socket for UDP connections, e.g. to wait for notifications with a timeout
+    pub fn udp(&self) -> Option<&UdpSocket> {
+        match &self.transport {
+            Transport::Udp(sock) => Some(sock),
+            _ => None,
+        }
+    }
+
+    pub fn send_frame(&self, frame: &[u8]) -> io::Result<usize> {
+        let sealed;
+        let frame = match &self.auth {
+            Some(auth) => {
+                sealed = auth.seal(frame);
+                sealed.as_slice()
+            }
+            None => frame,
+        };
+        match &self.transport {
+            Transport::Udp(sock) => sock.send(frame),
+            Transport::Tcp(stream) => {
+                msg::framing::write_frame(&mut &*stream, frame)?;
+                Ok(frame.len())
+            }
+            Transport::Unix(stream) => {
+                msg::framing::write_frame(&mut &*stream, frame)?;
+                Ok(frame.len())
+            }
+        }
+    }
+
+    pub fn recv_frame(&self) -> io::Result<Vec<u8>> {
+        let frame = match &self.transport {
+            Transport::Udp(sock) => {
+                let mut buf = vec![0; 65536];
+                let len = sock.recv(&mut buf)?;
+                buf.truncate(len);
+                buf
+            }
+            Transport::Tcp(stream) => msg::framing::read_frame(&mut &*stream)?,
+            Transport::Unix(stream) => msg::framing::read_frame(&mut &*stream)?,
+        };
+        // empty frames are error responses, the station has nothing to sign them with when it rejects a request
+        let Some(auth) = &self.auth else {
+            return Ok(frame);
+        };
+        if frame.is_empty() {
+            return Ok(frame);
+        }
+        if frame[0] != MessageId::Authenticated as u8 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Unauthenticated response from station"));
+        }
+        auth.open(&frame[1..]).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Sends a message without waiting for an answer
+    pub fn send<M: Message>(&self, msg: &M) -> Result<(), String> {
+        self.send_frame(&codec::encode(msg)).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    pub fn request<R: Request>(&self, req: &R) -> Result<R::Response, String> {
+        self.send(req)?;
+        let frame = self.recv_frame().map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => String::from("Timed out"),
+            _ => e.to_string(),
+        })?;
+        codec::decode(&frame)
+    }
+
+    pub fn get_status(&self, kind: StatusType) -> Result<String, String> {
+        Ok(self.request(&GetStatusReq::new(kind))?.status)
+    }
+
+    pub fn get_adc_value(&self, converted: bool, channel: u8, fresh: bool) -> Result<GetAdcValueResp, String> {
+        self.request(&GetAdcValueReq::new(converted, channel, fresh))
+    }
+
+    pub fn get_humidity(&self, channel: u8, fresh: bool) -> Result<GetHygrometerStatusResp, String> {
+        self.request(&GetHygrometerStatusReq::new(channel, fresh))
+    }
+
+    pub fn get_temperature(&self, fresh: bool) -> Result<GetTemperatureResp, String> {
+        self.request(&GetTemperatureReq::new(fresh))
+    }
+
+    pub fn scan_bus(&self, bus: &str, mux: Option<MuxChannel>) -> Result<Vec<ScannedDevice>, String> {
+        let resp = self.request(&ScanBusReq::new(bus.to_string(), mux))?;
+        if resp.error.is_empty() {
+            Ok(resp.devices)
+        } else {
+            Err(resp.error)
+        }
+    }
+
+    pub fn read_register(
+        &self,
+        bus: &str,
+        address: u16,
+        ten_bit: bool,
+        mux: Option<MuxChannel>,
+        register: u8,
+        length: u8,
+    ) -> Result<Vec<u8>, String> {
+        let resp = self.request(&ReadRegisterReq::new(bus.to_string(), address, ten_bit, mux, register, length))?;
+        if resp.error.is_empty() {
+            Ok(resp.data)
+        } else {
+            Err(resp.error)
+        }
+    }
+
+    pub fn write_register(
+        &self,
+        bus: &str,
+        address: u16,
+        ten_bit: bool,
+        mux: Option<MuxChannel>,
+        register: u8,
+        data: Vec<u8>,
+    ) -> Result<(), String> {
+        let resp = self.request(&WriteRegisterReq::new(bus.to_string(), address, ten_bit, mux, register, data))?;
+        if resp.error.is_empty() {
+            Ok(())
+        } else {
+            Err(resp.error)
+        }
+    }
+
+    /// Reads all sensors in one request, the readings carry their own errors
+    pub fn read_sensors(&self, sensors: Vec<String>, fresh: bool) -> Result<Vec<SensorReading>, String> {
+        let resp = self.request(&ReadSensorsReq::new(sensors, fresh))?;
+        if resp.error.is_empty() {
+            Ok(resp.readings)
+        } else {
+            Err(resp.error)
+        }
+    }
+
+    /// Requests every chunk of the history query and returns the buckets in order
+    pub fn get_history(&self, sensor: &str, from_ms: i64, to_ms: i64, resolution_ms: u32) -> Result<Vec<HistoryBucket>, String> {
+        let mut req = GetHistoryReq::new(sensor.to_string(), from_ms, to_ms, resolution_ms, 0);
+        let mut buckets = Vec::new();
+        loop {
+            let resp = self.request(&req)?;
+            if !resp.error.is_empty() {
+                return Err(resp.error);
+            }
+            buckets.extend(resp.buckets);
+            if req.chunk + 1 >= resp.chunks {
+                return Ok(buckets);
+            }
+            req.chunk += 1;
+        }
+    }
+}
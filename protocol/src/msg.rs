@@ -0,0 +1,78 @@
+use std::net::Ipv4Addr;
+
+pub mod auth;
+pub mod codec;
+pub mod framing;
+pub mod ps;
+
+/// Bumped whenever a message layout changes, reported in discovery responses
+pub const PROTOCOL_VERSION: u16 = 1;
+pub const DISCOVERY_PORT: u16 = 8081;
+pub const DISCOVERY_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 80, 83);
+
+// ids are given in declaration order starting at 0, NAMES is generated from the same list
+macro_rules! message_ids {
+    ($($(#[$attr:meta])* $id:ident),* $(,)?) => {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[repr(u8)]
+        pub enum MessageId {
+            $($(#[$attr])* $id,)*
+        }
+
+        impl MessageId {
+            /// Indexed by message id
+            pub const NAMES: &[&str] = &[$(stringify!($id),)*];
+        }
+    };
+}
+
+message_ids!(
+    Unknown,
+    GetStatusReq,
+    GetStatusResp,
+    GetAdcValueReq,
+    GetAdcValueResp,
+    GetHygrometerStatusReq,
+    GetHygrometerStatusResp,
+    GetTemperatureReq,
+    GetTemperatureResp,
+    ScanBusReq,
+    ScanBusResp,
+    ReadRegisterReq,
+    ReadRegisterResp,
+    WriteRegisterReq,
+    WriteRegisterResp,
+    GetHistoryReq,
+    GetHistoryResp,
+    SubscribeReq,
+    SubscribeResp,
+    UnsubscribeReq,
+    UnsubscribeResp,
+    ReadingNotification,
+    DiscoverReq,
+    DiscoverResp,
+    /// Envelope around any other message, see auth
+    Authenticated,
+    ReadSensorsReq,
+    ReadSensorsResp,
+);
+
+impl MessageId {
+    pub fn name(id: u8) -> &'static str {
+        Self::NAMES.get(id as usize).copied().unwrap_or("Unknown")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_follow_message_ids() {
+        assert_eq!(MessageId::Unknown as u8, 0);
+        assert_eq!(MessageId::NAMES.len(), MessageId::ReadSensorsResp as usize + 1);
+        assert_eq!(MessageId::name(MessageId::Authenticated as u8), "Authenticated");
+        assert_eq!(MessageId::name(MessageId::ReadSensorsResp as u8), "ReadSensorsResp");
+        assert_eq!(MessageId::name(MessageId::NAMES.len() as u8), "Unknown");
+    }
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use crate::msg::MessageId;
+
+pub const DEFAULT_WINDOW_MS: u32 = 30_000;
+
+/// Envelope of an authenticated frame, sent with MessageId::Authenticated in front
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthenticatedMsg {
+    timestamp_ms: i64, // unix time of the sender, must be within the replay window
+    nonce: u64, // random, each nonce is accepted once within the window
+    #[serde(with = "serde_bytes")]
+    frame: Vec<u8>, // message id followed by the bincode payload
+    #[serde(with = "serde_bytes")]
+    tag: Vec<u8>, // HMAC-SHA256 over timestamp, nonce and frame
+}
+
+/// Signs outgoing frames and verifies incoming ones with a pre-shared key
+pub struct Authenticator {
+    key: Vec<u8>,
+    window_ms: i64,
+    seen: Mutex<HashMap<u64, i64>>, // nonce -> timestamp of accepted messages
+}
+
+impl Authenticator {
+    pub fn new(key: &[u8], window_ms: u32) -> Authenticator {
+        Authenticator {
+            key: key.to_vec(),
+            window_ms: window_ms as i64,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn mac(&self, timestamp_ms: i64, nonce: u64, frame: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(&timestamp_ms.to_be_bytes());
+        mac.update(&nonce.to_be_bytes());
+        mac.update(frame);
+        mac
+    }
+
+    /// Wraps a complete frame (message id and payload) into an authenticated frame
+    pub fn seal(&self, frame: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; 8];
+        getrandom::getrandom(&mut nonce).expect("No random source for message nonces");
+        let nonce = u64::from_be_bytes(nonce);
+        let timestamp_ms = chrono::Utc::now().timestamp_millis();
+        let msg = AuthenticatedMsg {
+            timestamp_ms,
+            nonce,
+            frame: frame.to_vec(),
+            tag: self.mac(timestamp_ms, nonce, frame).finalize().into_bytes().to_vec(),
+        };
+        let mut out = bincode::serialize(&msg).expect("Authenticated message always serializes");
+        out.insert(0, MessageId::Authenticated as u8);
+        out
+    }
+
+    /// Verifies the payload of an authenticated frame and returns the inner frame
+    pub fn open(&self, buffer: &[u8]) -> Result<Vec<u8>, String> {
+        let msg: AuthenticatedMsg = bincode::deserialize(buffer).map_err(|e| e.to_string())?;
+        self.mac(msg.timestamp_ms, msg.nonce, &msg.frame)
+            .verify_slice(&msg.tag)
+            .map_err(|_| String::from("Message authentication failed"))?;
+
+        let now = chrono::Utc::now().timestamp_millis();
+        if (now - msg.timestamp_ms).abs() > self.window_ms {
+            return Err(format!("Message timestamp {} ms away from local clock", now - msg.timestamp_ms));
+        }
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, timestamp_ms| (now - *timestamp_ms).abs() <= self.window_ms);
+        if seen.insert(msg.nonce, msg.timestamp_ms).is_some() {
+            return Err(String::from("Replayed message"));
+        }
+        if msg.frame.is_empty() {
+            return Err(String::from("Empty authenticated frame"));
+        }
+        Ok(msg.frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"0123456789abcdef";
+    const WINDOW_MS: u32 = 1_000;
+
+    fn sealed_at(auth: &Authenticator, timestamp_ms: i64, frame: &[u8]) -> Vec<u8> {
+        let nonce = 42;
+        let msg = AuthenticatedMsg {
+            timestamp_ms,
+            nonce,
+            frame: frame.to_vec(),
+            tag: auth.mac(timestamp_ms, nonce, frame).finalize().into_bytes().to_vec(),
+        };
+        bincode::serialize(&msg).unwrap()
+    }
+
+    #[test]
+    fn seal_then_open_returns_frame() {
+        let auth = Authenticator::new(KEY, WINDOW_MS);
+        let frame = [MessageId::GetStatusReq as u8, 1];
+        let sealed = auth.seal(&frame);
+        assert_eq!(sealed[0], MessageId::Authenticated as u8);
+        assert_eq!(auth.open(&sealed[1..]).unwrap(), frame);
+    }
+
+    #[test]
+    fn tampered_tag_is_rejected() {
+        let auth = Authenticator::new(KEY, WINDOW_MS);
+        let mut sealed = auth.seal(&[MessageId::GetStatusReq as u8, 1]);
+        *sealed.last_mut().unwrap() ^= 0x01;
+        assert_eq!(auth.open(&sealed[1..]).unwrap_err(), "Message authentication failed");
+    }
+
+    #[test]
+    fn other_key_is_rejected() {
+        let sealed = Authenticator::new(KEY, WINDOW_MS).seal(&[MessageId::GetStatusReq as u8, 1]);
+        let auth = Authenticator::new(b"fedcba9876543210", WINDOW_MS);
+        assert_eq!(auth.open(&sealed[1..]).unwrap_err(), "Message authentication failed");
+    }
+
+    #[test]
+    fn replayed_nonce_is_rejected() {
+        let auth = Authenticator::new(KEY, WINDOW_MS);
+        let sealed = auth.seal(&[MessageId::GetStatusReq as u8, 1]);
+        assert!(auth.open(&sealed[1..]).is_ok());
+        assert_eq!(auth.open(&sealed[1..]).unwrap_err(), "Replayed message");
+    }
+
+    #[test]
+    fn timestamp_outside_window_is_rejected() {
+        let auth = Authenticator::new(KEY, WINDOW_MS);
+        let now = chrono::Utc::now().timestamp_millis();
+        for timestamp_ms in [now - 2 * WINDOW_MS as i64, now + 2 * WINDOW_MS as i64] {
+            let sealed = sealed_at(&auth, timestamp_ms, &[MessageId::GetStatusReq as u8, 1]);
+            let error = auth.open(&sealed).unwrap_err();
+            assert!(error.contains("away from local clock"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn empty_frame_is_rejected() {
+        let auth = Authenticator::new(KEY, WINDOW_MS);
+        let sealed = auth.seal(&[]);
+        assert_eq!(auth.open(&sealed[1..]).unwrap_err(), "Empty authenticated frame");
+        assert!(auth.open(&[]).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::msg::MessageId;
+use crate::msg::ps::*;
+
+/// Message with the id it is sent with
+pub trait Message: Serialize + DeserializeOwned {
+    const ID: MessageId;
+}
+
+/// Message the station answers with a response
+pub trait Request: Message {
+    type Response: Message;
+}
+
+macro_rules! messages {
+    ($($msg:ident),* $(,)?) => {
+        $(impl Message for $msg {
+            const ID: MessageId = MessageId::$msg;
+        })*
+    };
+}
+
+macro_rules! requests {
+    ($($req:ident => $resp:ident),* $(,)?) => {
+        $(impl Request for $req {
+            type Response = $resp;
+        })*
+    };
+}
+
+messages!(
+    GetStatusReq, GetStatusResp,
+    GetAdcValueReq, GetAdcValueResp,
+    GetHygrometerStatusReq, GetHygrometerStatusResp,
+    GetTemperatureReq, GetTemperatureResp,
+    ScanBusReq, ScanBusResp,
+    ReadRegisterReq, ReadRegisterResp,
+    WriteRegisterReq, WriteRegisterResp,
+    GetHistoryReq, GetHistoryResp,
+    SubscribeReq, SubscribeResp,
+    UnsubscribeReq, UnsubscribeResp,
+    ReadingNotification,
+    DiscoverReq, DiscoverResp,
+    ReadSensorsReq, ReadSensorsResp,
+);
+
+requests!(
+    GetStatusReq => GetStatusResp,
+    GetAdcValueReq => GetAdcValueResp,
+    GetHygrometerStatusReq => GetHygrometerStatusResp,
+    GetTemperatureReq => GetTemperatureResp,
+    ScanBusReq => ScanBusResp,
+    ReadRegisterReq => ReadRegisterResp,
+    WriteRegisterReq => WriteRegisterResp,
+    GetHistoryReq => GetHistoryResp,
+    SubscribeReq => SubscribeResp,
+    UnsubscribeReq => UnsubscribeResp,
+    DiscoverReq => DiscoverResp,
+    ReadSensorsReq => ReadSensorsResp,
+);
+
+/// Frame of a message: its id followed by the bincode payload
+pub fn encode<M: Message>(msg: &M) -> Vec<u8> {
+    let mut out = vec![M::ID as u8];
+    bincode::serialize_into(&mut out, msg).expect("Messages always serialize");
+    out
+}
+
+/// Splits a frame into message id and payload, None for empty error responses
+pub fn split(frame: &[u8]) -> Option<(u8, &[u8])> {
+    frame.split_first().map(|(msg_id, payload)| (*msg_id, payload))
+}
+
+/// Decodes the payload of a frame whose id was already checked
+pub fn decode_payload<M: Message>(payload: &[u8]) -> Result<M, String> {
+    bincode::deserialize(payload).map_err(|e| format!("{} error: {}", MessageId::name(M::ID as u8), e))
+}
+
+/// Decodes a complete frame, failing if it carries a different message
+pub fn decode<M: Message>(frame: &[u8]) -> Result<M, String> {
+    match split(frame) {
+        None => Err(String::from("Error response received")),
+        Some((msg_id, payload)) if msg_id == M::ID as u8 => decode_payload(payload),
+        Some((msg_id, _)) => Err(format!(
+            "Expected {}, received {}",
+            MessageId::name(M::ID as u8),
+            MessageId::name(msg_id)
+        )),
+    }
+}
@@ -0,0 +1,27 @@
+use std::io::{self, Read, Write};
+
+/// Largest frame accepted over stream transports
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// Writes the frame (message id followed by the bincode payload) prefixed with its u32 big-endian length
+pub fn write_frame(stream: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+    if frame.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Frame too long"));
+    }
+    let mut out = (frame.len() as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(frame);
+    stream.write_all(&out)?;
+    stream.flush()
+}
+
+pub fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len_bytes = [0; 4];
+    stream.read_exact(&mut len_bytes)?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too long"));
+    }
+    let mut frame = vec![0; len];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
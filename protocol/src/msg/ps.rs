@@ -0,0 +1,235 @@
+use serde::{Serialize, Deserialize};
+use derive_new::new;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum StatusType {
+    Unknown,
+    I2C,
+    ADC,
+    Access, // allowlist and rate limit counters
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GetStatusReq {
+    status_type: u8,
+}
+
+impl GetStatusReq {
+    pub fn new(status_type_arg: StatusType) -> GetStatusReq {
+        GetStatusReq { status_type: status_type_arg as u8 }
+    }
+
+    pub fn get_status(&self) -> StatusType {
+        match self.status_type {
+            1 => StatusType::I2C,
+            2 => StatusType::ADC,
+            3 => StatusType::Access,
+            _ => StatusType::Unknown
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetStatusResp {
+    pub status: String
+}
+
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetAdcValueReq {
+    converted: bool,
+    pub channel: u8, // mux bitmap 0=0b000, 1=0b001,...., 7=0b111
+    pub fresh: bool, // skip the sampling cache and read the sensor now
+}
+impl GetAdcValueReq {
+    pub fn is_converted(&self) -> bool {
+        self.converted
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetAdcValueResp {
+    pub value: u16,
+    pub age_ms: u32, // time since the value was read from the sensor
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetHygrometerStatusReq {
+    pub channel: u8, // mux bitmap 0=0b000, 1=0b001,...., 7=0b111
+    pub fresh: bool, // skip the sampling cache and read the sensor now
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetHygrometerStatusResp {
+    pub humidity: u8,
+    pub age_ms: u32, // time since the value was read from the sensor
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetTemperatureReq {
+    pub fresh: bool, // skip the sampling cache and read the sensor now
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetTemperatureResp {
+    pub temperature: i16,
+    pub age_ms: u32, // time since the value was read from the sensor
+}
+
+/// Multiplexer channel opened for a debug request, to reach devices placed behind it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, new)]
+pub struct MuxChannel {
+    pub mux_address: u16,
+    pub channel: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ScanBusReq {
+    pub bus: String, // i2c device path, empty for the default bus
+    pub mux: Option<MuxChannel>, // all multiplexers are closed if not given
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ScannedDevice {
+    pub address: u16,
+    pub device: String, // name of the configured device at this address, empty if unknown
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ScanBusResp {
+    pub devices: Vec<ScannedDevice>,
+    pub error: String, // empty on success
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ReadRegisterReq {
+    pub bus: String, // i2c device path, empty for the default bus
+    pub address: u16,
+    pub ten_bit: bool,
+    pub mux: Option<MuxChannel>, // all multiplexers are closed if not given
+    #[serde(rename = "reg")] // register is a keyword in the generated C++ bindings
+    pub register: u8,
+    pub length: u8, // 1..=4 bytes
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ReadRegisterResp {
+    pub data: Vec<u8>,
+    pub error: String, // empty on success
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct WriteRegisterReq {
+    pub bus: String, // i2c device path, empty for the default bus
+    pub address: u16,
+    pub ten_bit: bool,
+    pub mux: Option<MuxChannel>, // all multiplexers are closed if not given
+    #[serde(rename = "reg")] // register is a keyword in the generated C++ bindings
+    pub register: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct WriteRegisterResp {
+    pub error: String, // empty on success
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetHistoryReq {
+    pub sensor: String, // e.g. "temperature", "humidity:0", "adc_mv:1"
+    pub from_ms: i64, // unix time
+    pub to_ms: i64, // unix time
+    pub resolution_ms: u32, // bucket length
+    pub chunk: u16, // index of the response chunk to return
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct HistoryBucket {
+    pub start_ms: i64,
+    pub min: i32,
+    pub max: i32,
+    pub avg: i32,
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct GetHistoryResp {
+    pub chunk: u16,
+    pub chunks: u16, // total number of chunks for the query
+    pub buckets: Vec<HistoryBucket>,
+    pub error: String, // empty on success
+}
+
+/// New subscriptions push nothing until they are renewed once with the returned id, which proves
+/// the subscriber receives datagrams at the address the request came from
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct SubscribeReq {
+    pub subscription_id: u32, // 0 for a new subscription, otherwise renews the lease of an existing one
+    pub sensors: Vec<String>, // e.g. "temperature", "humidity:0", "adc_mv:1"
+    pub interval_ms: u32, // how often the sensors are checked
+    pub on_change_threshold: u32, // 0 notifies every interval, otherwise only when a value moved at least this much
+    pub lease_ms: u32, // subscription expires unless renewed within this time, 0 for the station default
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct SubscribeResp {
+    pub subscription_id: u32,
+    pub lease_ms: u32, // lease granted by the station, may be shorter than requested
+    pub error: String, // empty on success
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct UnsubscribeReq {
+    pub subscription_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct UnsubscribeResp {
+    pub error: String, // empty on success
+}
+
+/// Value or error of a single sensor, as pushed in notifications and returned by ReadSensorsReq
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct SensorReading {
+    pub sensor: String,
+    pub value: i32,
+    pub age_ms: u32, // time since the value was read from the sensor
+    pub error: String, // empty if the sensor was read successfully
+}
+
+/// Pushed by the station to subscribers, never sent as a response
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ReadingNotification {
+    pub subscription_id: u32,
+    pub readings: Vec<SensorReading>,
+}
+
+/// Broadcast or multicast to the discovery port, every station on the network answers
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct DiscoverReq {
+    pub protocol_version: u16, // of the sender
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct DiscoverResp {
+    pub name: String,
+    pub id: String,
+    pub address: String, // address the station listens on, unspecified means use the sender of this response
+    pub port: u16, // request port for UDP, and TCP when enabled
+    pub protocol_version: u16,
+    pub capabilities: Vec<String>, // e.g. "tcp", "http:8000", "history", "mqtt", "subscriptions"
+}
+
+/// Reads several sensors in one round trip, each reading carries its own error
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ReadSensorsReq {
+    pub sensors: Vec<String>, // e.g. "temperature", "humidity:0", "adc_mv:1"
+    pub fresh: bool, // read all sensors now, back to back, instead of returning cached samples
+}
+
+#[derive(Serialize, Deserialize, Debug, new)]
+pub struct ReadSensorsResp {
+    pub readings: Vec<SensorReading>, // in the order of the request
+    pub error: String, // empty unless the whole request was rejected
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Instant;
+use crate::app_context::AccessConfig;
+
+const MAX_TRACKED_SOURCES: usize = 1024;
+
+/// Address range such as 192.168.1.0/24 or fd00::/8, a bare address matches only itself
+#[derive(Clone, Copy, Debug)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(cidr: &str) -> Result<Cidr, String> {
+        let (address, prefix) = match cidr.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (cidr, None),
+        };
+        let network: IpAddr = address.parse().map_err(|_| format!("Invalid address in {}", cidr))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| format!("Invalid prefix in {}", cidr))?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(format!("Prefix of {} longer than the address", cidr));
+        }
+        // sources are compared in canonical form, so v4-mapped ranges become v4 ranges
+        const MAPPED_PREFIX: u8 = 96;
+        match network.to_canonical() {
+            IpAddr::V4(v4) if network.is_ipv6() && prefix >= MAPPED_PREFIX => {
+                Ok(Cidr { network: IpAddr::V4(v4), prefix: prefix - MAPPED_PREFIX })
+            }
+            _ => Ok(Cidr { network, prefix }),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AccessCounters {
+    pub accepted: u64,
+    pub denied: u64, // source outside of the allowlist
+    pub rate_limited: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    Denied,
+    RateLimited,
+}
+
+struct Source {
+    tokens: f64,
+    refilled_at: Instant,
+    counters: AccessCounters,
+}
+
+/// Drops the least recently seen quarter of the sources. Freeing many at once keeps a flood from
+/// many addresses from scanning the whole map for every new one.
+fn evict_least_recent(sources: &mut HashMap<IpAddr, Source>) {
+    let mut seen: Vec<Instant> = sources.values().map(|source| source.refilled_at).collect();
+    let (_, cutoff, _) = seen.select_nth_unstable(sources.len() / 4);
+    let cutoff = *cutoff;
+    sources.retain(|_, source| source.refilled_at > cutoff);
+}
+
+/// Allowlist and per-source token bucket, checked before a request takes a worker or the I2C bus
+pub struct AccessControl {
+    config: AccessConfig,
+    sources: Mutex<HashMap<IpAddr, Source>>,
+    totals: Mutex<AccessCounters>,
+}
+
+impl AccessControl {
+    pub fn new(config: AccessConfig) -> AccessControl {
+        AccessControl {
+            config,
+            sources: Mutex::new(HashMap::new()),
+            totals: Mutex::new(AccessCounters::default()),
+        }
+    }
+
+    fn allowed(&self, ip: IpAddr) -> bool {
+        self.config.allow.is_empty() || self.config.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    pub fn check(&self, ip: IpAddr) -> Result<(), Rejection> {
+        let now = Instant::now();
+        let result = if !self.allowed(ip) {
+            Err(Rejection::Denied)
+        } else {
+            self.take_token(ip, now)
+        };
+
+        let mut totals = self.totals.lock().unwrap();
+        match result {
+            Ok(_) => totals.accepted += 1,
+            Err(Rejection::Denied) => totals.denied += 1,
+            Err(Rejection::RateLimited) => totals.rate_limited += 1,
+        }
+        result
+    }
+
+    fn take_token(&self, ip: IpAddr, now: Instant) -> Result<(), Rejection> {
+        let Some(limit) = self.config.rate_limit else {
+            return Ok(());
+        };
+        let burst = limit.burst as f64;
+        let mut sources = self.sources.lock().unwrap();
+        if sources.len() >= MAX_TRACKED_SOURCES && !sources.contains_key(&ip) {
+            evict_least_recent(&mut sources);
+        }
+        let source = sources.entry(ip).or_insert(Source {
+            tokens: burst,
+            refilled_at: now,
+            counters: AccessCounters::default(),
+        });
+        let elapsed = now.saturating_duration_since(source.refilled_at).as_secs_f64();
+        source.tokens = (source.tokens + elapsed * limit.requests_per_s).min(burst);
+        source.refilled_at = now;
+        if source.tokens < 1.0 {
+            source.counters.rate_limited += 1;
+            return Err(Rejection::RateLimited);
+        }
+        source.tokens -= 1.0;
+        source.counters.accepted += 1;
+        Ok(())
+    }
+
+    pub fn counters(&self) -> AccessCounters {
+        *self.totals.lock().unwrap()
+    }
+
+    pub fn status(&self) -> String {
+        let totals = self.counters();
+        let mut lines = vec![format!(
+            "accepted: {}, denied: {}, rate limited: {}",
+            totals.accepted, totals.denied, totals.rate_limited
+        )];
+        let sources = self.sources.lock().unwrap();
+        let mut limited: Vec<(&IpAddr, &Source)> =
+            sources.iter().filter(|(_, source)| source.counters.rate_limited > 0).collect();
+        limited.sort_by_key(|(_, source)| std::cmp::Reverse(source.counters.rate_limited));
+        for (ip, source) in limited {
+            lines.push(format!(
+                "{} | accepted: {}, rate limited: {}",
+                ip, source.counters.accepted, source.counters.rate_limited
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+    use super::*;
+    use crate::app_context::RateLimitConfig;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_zero_prefix_matches_everything_of_its_family() {
+        let any_v4 = Cidr::parse("0.0.0.0/0").unwrap();
+        assert!(any_v4.contains(ip("10.1.2.3")));
+        assert!(any_v4.contains(ip("255.255.255.255")));
+        assert!(!any_v4.contains(ip("fd00::1")));
+        let any_v6 = Cidr::parse("::/0").unwrap();
+        assert!(any_v6.contains(ip("fd00::1")));
+    }
+
+    #[test]
+    fn cidr_full_prefix_matches_one_address() {
+        let host = Cidr::parse("192.168.1.10/32").unwrap();
+        assert!(host.contains(ip("192.168.1.10")));
+        assert!(!host.contains(ip("192.168.1.11")));
+        let bare = Cidr::parse("192.168.1.10").unwrap();
+        assert!(bare.contains(ip("192.168.1.10")));
+        assert!(!bare.contains(ip("192.168.1.11")));
+    }
+
+    #[test]
+    fn cidr_matches_v4_mapped_v6_addresses() {
+        let lan = Cidr::parse("192.168.1.0/24").unwrap();
+        assert!(lan.contains(ip("::ffff:192.168.1.20")));
+        assert!(!lan.contains(ip("::ffff:192.168.2.20")));
+        let mapped_lan = Cidr::parse("::ffff:192.168.1.0/120").unwrap();
+        assert!(mapped_lan.contains(ip("192.168.1.20")));
+        assert!(mapped_lan.contains(ip("::ffff:192.168.1.20")));
+        assert!(!mapped_lan.contains(ip("192.168.2.20")));
+    }
+
+    #[test]
+    fn cidr_rejects_prefix_longer_than_address() {
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("fd00::/129").is_err());
+        assert!(Cidr::parse("10.0.0.0/x").is_err());
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let access = AccessControl::new(AccessConfig {
+            allow: Vec::new(),
+            rate_limit: Some(RateLimitConfig { requests_per_s: 10.0, burst: 2 }),
+        });
+        let source = ip("10.0.0.1");
+        let start = Instant::now();
+        assert_eq!(access.take_token(source, start), Ok(()));
+        assert_eq!(access.take_token(source, start), Ok(()));
+        assert_eq!(access.take_token(source, start), Err(Rejection::RateLimited));
+        // other sources have their own bucket
+        assert_eq!(access.take_token(ip("10.0.0.2"), start), Ok(()));
+
+        let refilled = start + Duration::from_millis(100);
+        assert_eq!(access.take_token(source, refilled), Ok(()));
+        assert_eq!(access.take_token(source, refilled), Err(Rejection::RateLimited));
+
+        // a long pause refills no more than the burst
+        let idle = refilled + Duration::from_secs(10);
+        assert_eq!(access.take_token(source, idle), Ok(()));
+        assert_eq!(access.take_token(source, idle), Ok(()));
+        assert_eq!(access.take_token(source, idle), Err(Rejection::RateLimited));
+    }
+
+    #[test]
+    fn tracked_sources_stay_within_the_cap() {
+        let access = AccessControl::new(AccessConfig {
+            allow: Vec::new(),
+            rate_limit: Some(RateLimitConfig { requests_per_s: 0.1, burst: 1 }),
+        });
+        let start = Instant::now();
+        let limited = ip("10.0.0.1");
+        assert_eq!(access.take_token(limited, start), Ok(()));
+        for n in 0..2 * MAX_TRACKED_SOURCES as u32 {
+            let source = IpAddr::from(Ipv4Addr::from(0x0B00_0000 + n));
+            let now = start + Duration::from_millis(n as u64 + 1);
+            assert_eq!(access.take_token(source, now), Ok(()));
+            assert!(access.sources.lock().unwrap().len() <= MAX_TRACKED_SOURCES);
+            // a source sending all along is not evicted and keeps its empty bucket
+            assert_eq!(access.take_token(limited, now), Err(Rejection::RateLimited));
+        }
+    }
+
+    #[test]
+    fn denied_sources_do_not_use_tokens() {
+        let access = AccessControl::new(AccessConfig {
+            allow: vec![Cidr::parse("10.0.0.0/8").unwrap()],
+            rate_limit: Some(RateLimitConfig { requests_per_s: 1.0, burst: 1 }),
+        });
+        assert_eq!(access.check(ip("192.168.1.1")), Err(Rejection::Denied));
+        assert_eq!(access.check(ip("10.0.0.1")), Ok(()));
+        let counters = access.counters();
+        assert_eq!((counters.accepted, counters.denied), (1, 1));
+    }
+}
@@ -2,6 +2,7 @@ use json;
 use std::fs;
 use std::collections::HashMap;
 use json::JsonValue;
+use crate::access::Cidr;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum AdcSupported {
@@ -15,19 +16,147 @@ pub enum ThermometerSupported {
     LPS331AP,
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum MuxSupported {
+    Unknown,
+    TCA9548A,
+    PCA9548,
+}
+
+#[derive(Clone)]
+pub struct MuxConfig {
+    pub mux_type: MuxSupported,
+    pub bus: String,
+    pub address: u16,
+    pub channels: u8,
+}
+
+/// Location of a device placed behind an I2C multiplexer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MuxRoute {
+    pub mux_address: u16,
+    pub channel: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum I2cTraceMode {
+    Disabled,
+    Record(String), // path of the trace file to write
+    Replay(String), // path of a recorded trace used instead of real buses
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Sensor {
+    Temperature,
+    Pressure,          // hPa, from the thermometer chip
+    Humidity(u8),      // adc channel of the hygrometer, see AdcConfig::CHANNELS
+    AdcRaw(u8),        // adc channel
+    AdcMillivolts(u8), // adc channel
+}
+
+impl Sensor {
+    pub fn name(&self) -> String {
+        match self {
+            Sensor::Temperature => String::from("temperature"),
+            Sensor::Pressure => String::from("pressure"),
+            Sensor::Humidity(channel) => format!("humidity:{}", channel),
+            Sensor::AdcRaw(channel) => format!("adc_raw:{}", channel),
+            Sensor::AdcMillivolts(channel) => format!("adc_mv:{}", channel),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Sensor> {
+        let (kind, channel) = match name.split_once(':') {
+            Some((kind, channel)) => (kind, channel.parse::<u8>().ok()),
+            None => (name, None),
+        };
+        match (kind, channel) {
+            ("temperature", None) => Some(Sensor::Temperature),
+            ("pressure", None) => Some(Sensor::Pressure),
+            ("humidity", Some(channel)) => Some(Sensor::Humidity(channel)),
+            ("adc_raw", Some(channel)) => Some(Sensor::AdcRaw(channel)),
+            ("adc_mv", Some(channel)) => Some(Sensor::AdcMillivolts(channel)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SamplingConfig {
+    pub sensor: Sensor,
+    pub interval_ms: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct HistoryConfig {
+    pub path: String,
+    pub retention_days: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct StationIdentity {
+    pub name: String,
+    pub id: String, // stable across restarts, unlike the address
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitConfig {
+    pub requests_per_s: f64,
+    pub burst: u32, // requests accepted at once after a quiet period
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AccessConfig {
+    pub allow: Vec<Cidr>, // empty allows every source
+    pub rate_limit: Option<RateLimitConfig>, // per source address
+}
+
+#[derive(Clone)]
+pub struct AuthConfig {
+    pub psk: Vec<u8>,
+    pub window_ms: u32, // accepted clock difference, nonces are remembered for this long
+    pub required: bool, // reject requests without a valid HMAC
+}
+
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub topic_prefix: String,
+    pub interval_ms: u64,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub discovery_prefix: Option<String>, // Home Assistant discovery is only published when set
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RecoveryConfig {
+    pub retries: u8,
+    pub backoff_ms: u64,
+    pub stuck_threshold: u32, // consecutive failed transactions before the bus is reopened
+}
+
 #[derive(Clone)]
 pub struct DeviceData {
+    pub bus: String,
     pub address: u16,
+    pub ten_bit_address: bool,
+    pub pec: bool,
+    pub mux: Option<MuxRoute>,
     pub registers: HashMap<String, u8>,
     pub registers_values: HashMap<String, Vec<u8>>,
 }
 
 #[derive(Clone)]
 pub struct AdcConfig {
-    pub adc_address: u16,
     pub adc_type: AdcSupported,
-    pub registers: HashMap<String, u8>,
-    pub registers_values: HashMap<String, Vec<u8>>,
+    pub device_data: DeviceData,
+}
+
+impl AdcConfig {
+    /// Input multiplexer settings of each ADC, sensor channels count on across the configured ADCs
+    pub const CHANNELS: u8 = 8;
 }
 
 #[derive(Clone)]
@@ -38,8 +167,18 @@ pub struct ThermometerConfig {
 
 #[derive(Clone)]
 pub struct AppContext {
+    pub identity: StationIdentity,
     pub i2c_dev_path: String,
-    pub adc_config: AdcConfig,
+    pub register_access: bool,
+    pub i2c_recovery: RecoveryConfig,
+    pub i2c_trace: I2cTraceMode,
+    pub sampling: Vec<SamplingConfig>,
+    pub history: Option<HistoryConfig>,
+    pub mqtt: Option<MqttConfig>,
+    pub auth: Option<AuthConfig>,
+    pub access: AccessConfig,
+    pub muxes: Vec<MuxConfig>,
+    pub adcs: Vec<AdcConfig>,
     pub thermometer_config: ThermometerConfig,
 }
 
@@ -57,6 +196,180 @@ fn get_thermometer_type(type_str: &str) -> ThermometerSupported {
     }
 }
 
+fn get_mux_type(type_str: &str) -> MuxSupported {
+    match type_str.to_uppercase().as_str() {
+        "TCA9548A" => MuxSupported::TCA9548A,
+        "PCA9548" => MuxSupported::PCA9548,
+        _ => MuxSupported::Unknown,
+    }
+}
+
+fn get_recovery_config(recovery: &JsonValue) -> RecoveryConfig {
+    RecoveryConfig {
+        retries: recovery["retries"].as_u8().unwrap_or(2),
+        backoff_ms: recovery["backoff_ms"].as_u64().unwrap_or(5),
+        stuck_threshold: recovery["stuck_threshold"].as_u32().unwrap_or(3).max(1),
+    }
+}
+
+fn get_sensor(sensor: &JsonValue) -> Sensor {
+    let channel = || sensor["channel"].as_u8().expect("Sensor channel not defined");
+    match sensor["sensor"].as_str().unwrap().to_lowercase().as_str() {
+        "temperature" => Sensor::Temperature,
+        "pressure" => Sensor::Pressure,
+        "humidity" => Sensor::Humidity(channel()),
+        "adc_raw" => Sensor::AdcRaw(channel()),
+        "adc_mv" => Sensor::AdcMillivolts(channel()),
+        other => panic!("Unsupported sensor {} in sampling configuration", other),
+    }
+}
+
+fn get_sampling(sampling: &JsonValue) -> Vec<SamplingConfig> {
+    sampling
+        .members()
+        .map(|entry| SamplingConfig {
+            sensor: get_sensor(entry),
+            interval_ms: entry["interval_ms"].as_u64().unwrap().max(1),
+        })
+        .collect()
+}
+
+fn get_history_config(history: &JsonValue) -> Option<HistoryConfig> {
+    if history.is_null() {
+        return None;
+    }
+    Some(HistoryConfig {
+        path: history["path"].as_str().expect("History path not defined").to_string(),
+        retention_days: history["retention_days"].as_u32().unwrap_or(30),
+    })
+}
+
+fn read_trimmed(path: &str) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let trimmed = content.trim();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+// defaults to the hostname and machine id, so stations need no per-device configuration
+fn get_identity(station: &JsonValue) -> StationIdentity {
+    let name = station["name"]
+        .as_str()
+        .map(|name| name.to_string())
+        .or_else(|| read_trimmed("/etc/hostname"))
+        .unwrap_or_else(|| String::from("plantstation"));
+    let id = station["id"]
+        .as_str()
+        .map(|id| id.to_string())
+        .or_else(|| read_trimmed("/etc/machine-id"))
+        .unwrap_or_else(|| name.clone());
+    StationIdentity { name, id }
+}
+
+fn get_mqtt_config(mqtt: &JsonValue) -> Option<MqttConfig> {
+    if mqtt.is_null() {
+        return None;
+    }
+    Some(MqttConfig {
+        host: mqtt["host"].as_str().expect("MQTT host not defined").to_string(),
+        port: mqtt["port"].as_u16().unwrap_or(1883),
+        client_id: mqtt["client_id"].as_str().unwrap_or("plantstation").to_string(),
+        topic_prefix: mqtt["topic_prefix"].as_str().unwrap_or("plantstation").trim_end_matches('/').to_string(),
+        interval_ms: mqtt["interval_ms"].as_u64().unwrap_or(30000).max(1),
+        username: mqtt["username"].as_str().map(|username| username.to_string()),
+        password: mqtt["password"].as_str().map(|password| password.to_string()),
+        discovery_prefix: mqtt["discovery_prefix"].as_str().map(|prefix| prefix.trim_end_matches('/').to_string()),
+    })
+}
+
+fn get_auth_config(auth: &JsonValue) -> Option<AuthConfig> {
+    if auth.is_null() {
+        return None;
+    }
+    let psk = auth["psk"].as_str().expect("Authentication psk not defined");
+    if psk.len() < 16 {
+        panic!("Authentication psk must be at least 16 characters");
+    }
+    Some(AuthConfig {
+        psk: psk.as_bytes().to_vec(),
+        window_ms: auth["window_ms"].as_u32().unwrap_or(plantstation_protocol::msg::auth::DEFAULT_WINDOW_MS),
+        required: auth["required"].as_bool().unwrap_or(true),
+    })
+}
+
+fn get_access_config(access: &JsonValue) -> AccessConfig {
+    let allow = access["allow"]
+        .members()
+        .map(|cidr| Cidr::parse(cidr.as_str().expect("Allowlist entries must be strings")).unwrap_or_else(|e| panic!("{}", e)))
+        .collect();
+    let rate_limit = &access["rate_limit"];
+    AccessConfig {
+        allow,
+        rate_limit: (!rate_limit.is_null()).then(|| RateLimitConfig {
+            requests_per_s: rate_limit["requests_per_s"].as_f64().expect("Rate limit requests_per_s not defined"),
+            burst: rate_limit["burst"].as_u32().unwrap_or(1).max(1),
+        }),
+    }
+}
+
+fn get_bus(device: &JsonValue, default_bus: &str) -> String {
+    device["i2cdev"].as_str().unwrap_or(default_bus).to_string()
+}
+
+fn get_muxes(muxes: &JsonValue, default_bus: &str) -> Vec<MuxConfig> {
+    const MUX_CHANNELS: u8 = 8;
+    let mut ret = Vec::new();
+    for mux in muxes.members() {
+        let mux_type = get_mux_type(mux["type"].as_str().unwrap());
+        if mux_type == MuxSupported::Unknown {
+            panic!("Unsupported I2C multiplexer type in configuration");
+        }
+        ret.push(MuxConfig {
+            mux_type,
+            bus: get_bus(mux, default_bus),
+            address: mux["i2c_address"].as_u16().unwrap(),
+            channels: MUX_CHANNELS,
+        });
+    }
+    ret
+}
+
+fn get_mux_route(route: &JsonValue, bus: &str, muxes: &[MuxConfig]) -> Option<MuxRoute> {
+    if route.is_null() {
+        return None;
+    }
+    let mux_address = route["i2c_address"].as_u16().unwrap();
+    let channel = route["channel"].as_u8().unwrap();
+    match muxes.iter().find(|mux| mux.bus == bus && mux.address == mux_address) {
+        Some(mux) if channel < mux.channels => Some(MuxRoute { mux_address, channel }),
+        Some(_) => panic!("Channel {} not available on multiplexer {:#x}", channel, mux_address),
+        None => panic!("Multiplexer {:#x} on {} not defined in configuration", mux_address, bus),
+    }
+}
+
+fn get_adc_config(adc: &JsonValue, default_bus: &str, muxes: &[MuxConfig]) -> AdcConfig {
+    let adc_type = get_adc_type(adc["type"].as_str().unwrap());
+    if adc_type == AdcSupported::Unknown {
+        panic!("Unsupported ADC type in configuration");
+    }
+    AdcConfig {
+        adc_type,
+        device_data: get_device_data(adc, default_bus, muxes),
+    }
+}
+
+// "adcs" lists several ADCs, a single "adc" object is still accepted
+fn get_adcs(parsed: &JsonValue, default_bus: &str, muxes: &[MuxConfig]) -> Vec<AdcConfig> {
+    let adcs: Vec<AdcConfig> = if parsed["adcs"].is_null() {
+        vec![get_adc_config(&parsed["adc"], default_bus, muxes)]
+    } else {
+        parsed["adcs"].members().map(|adc| get_adc_config(adc, default_bus, muxes)).collect()
+    };
+    if adcs.is_empty() || adcs.len() * AdcConfig::CHANNELS as usize > 256 {
+        panic!("Between 1 and {} ADCs must be configured", 256 / AdcConfig::CHANNELS as usize);
+    }
+    adcs
+}
+
 fn get_registers(reg_values: &JsonValue) -> HashMap<String, u8> {
     let mut ret = HashMap::new();
     for (key, value) in  reg_values.entries(){
@@ -79,26 +392,91 @@ fn get_registers_values(reg_values: &JsonValue) -> HashMap<String, Vec<u8>> {
     ret
 }
 
+fn get_device_data(device: &JsonValue, default_bus: &str, muxes: &[MuxConfig]) -> DeviceData {
+    let bus = get_bus(device, default_bus);
+    let address = device["i2c_address"].as_u16().unwrap();
+    let ten_bit_address = device["ten_bit_address"].as_bool().unwrap_or(false);
+    if !ten_bit_address && address > 0x7F {
+        panic!("I2C address {:#x} does not fit in 7 bits, set ten_bit_address", address);
+    }
+    if ten_bit_address && address > 0x3FF {
+        panic!("I2C address {:#x} does not fit in 10 bits", address);
+    }
+    DeviceData {
+        mux: get_mux_route(&device["mux"], &bus, muxes),
+        bus,
+        address,
+        ten_bit_address,
+        pec: device["pec"].as_bool().unwrap_or(false),
+        registers: get_registers(&device["registers"]),
+        registers_values: get_registers_values(&device["registers_values"]),
+    }
+}
+
 impl AppContext {
     pub fn new(config_path: String) -> AppContext {
         let file: String = fs::read_to_string(&config_path).unwrap();
         let parsed = json::parse(&file).unwrap();
+        let i2c_dev_path = parsed["i2cdev"].as_str().unwrap().to_string();
+        let muxes = get_muxes(&parsed["muxes"], &i2c_dev_path);
         AppContext {
-            i2c_dev_path: parsed["i2cdev"].as_str().unwrap().to_string(),
-            adc_config: AdcConfig {
-                adc_address: parsed["adc"]["i2c_address"].as_u16().unwrap(),
-                adc_type: get_adc_type(parsed["adc"]["type"].as_str().unwrap()),
-                registers: get_registers(&parsed["adc"]["registers"]),
-                registers_values: get_registers_values(&parsed["adc"]["registers_values"]),
-            },
+            identity: get_identity(&parsed["station"]),
+            register_access: parsed["register_access"].as_bool().unwrap_or(false),
+            i2c_recovery: get_recovery_config(&parsed["i2c_recovery"]),
+            i2c_trace: I2cTraceMode::Disabled,
+            sampling: get_sampling(&parsed["sampling"]),
+            history: get_history_config(&parsed["history"]),
+            mqtt: get_mqtt_config(&parsed["mqtt"]),
+            auth: get_auth_config(&parsed["auth"]),
+            access: get_access_config(&parsed["access"]),
+            adcs: get_adcs(&parsed, &i2c_dev_path, &muxes),
             thermometer_config: ThermometerConfig {
                 thermometer_type: get_thermometer_type(parsed["thermometer"]["type"].as_str().unwrap()),
-                device_data: DeviceData {
-                    address: parsed["thermometer"]["i2c_address"].as_u16().unwrap(),
-                    registers : get_registers(&parsed["thermometer"]["registers"]),
-                    registers_values : get_registers_values(&parsed["thermometer"]["registers_values"]),
-                }
+                device_data: get_device_data(&parsed["thermometer"], &i2c_dev_path, &muxes),
             },
+            muxes,
+            i2c_dev_path,
+        }
+    }
+
+    /// Temperature, pressure and every sampled sensor, without duplicates
+    pub fn sensors(&self) -> Vec<Sensor> {
+        let mut sensors = vec![Sensor::Temperature, Sensor::Pressure];
+        for config in self.sampling.iter() {
+            if !sensors.contains(&config.sensor) {
+                sensors.push(config.sensor);
+            }
+        }
+        sensors
+    }
+
+    /// Short description of the configured devices, e.g. "ADS1115 + LPS331AP"
+    pub fn hardware_model(&self) -> String {
+        let mut devices: Vec<String> = self.adcs.iter().map(|adc| format!("{:?}", adc.adc_type)).collect();
+        devices.push(format!("{:?}", self.thermometer_config.thermometer_type));
+        devices.join(" + ")
+    }
+
+    /// ADC wired to a sensor channel and the channel on that ADC
+    pub fn adc_for_channel(&self, channel: u8) -> Option<(usize, u8)> {
+        let index = (channel / AdcConfig::CHANNELS) as usize;
+        (index < self.adcs.len()).then_some((index, channel % AdcConfig::CHANNELS))
+    }
+
+    /// All I2C buses used by the configured devices, default bus first
+    pub fn i2c_buses(&self) -> Vec<String> {
+        let mut buses = vec![self.i2c_dev_path.clone()];
+        let used = self
+            .muxes
+            .iter()
+            .map(|mux| &mux.bus)
+            .chain(self.adcs.iter().map(|adc| &adc.device_data.bus))
+            .chain([&self.thermometer_config.device_data.bus]);
+        for bus in used {
+            if !buses.contains(bus) {
+                buses.push(bus.clone());
+            }
         }
+        buses
     }
 }
@@ -1,11 +1,16 @@
 use std::fs::File;
-use std::io::Write;
-use std::net::{SocketAddr, UdpSocket};
-use clap::{Parser, arg};
+use std::io::{ErrorKind, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use clap::{Parser, Subcommand, arg};
 use String;
-use crate::msg::ps::StatusType;
+use plantstation_protocol::client::Client;
+use plantstation_protocol::msg;
+use plantstation_protocol::msg::auth::Authenticator;
+use plantstation_protocol::msg::codec::{self, Message};
+use plantstation_protocol::msg::ps::StatusType;
 
-mod msg;
+mod fleet;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -17,120 +22,555 @@ struct Args {
     #[arg(long, default_value = "8080")]
     ps_port: u16,
 
-    #[arg(long, default_value = "192.168.1.55")]
+    /// Local address to send from, all interfaces by default
+    #[arg(long, default_value = "0.0.0.0")]
     ctrl_addr: String,
 
-    #[arg(long, default_value = "8080")]
+    /// Local port to send from, any free port by default
+    #[arg(long, default_value = "0")]
     ctrl_port: u16,
+
+    /// Talk to the station over TCP instead of UDP
+    #[arg(long, default_value_t = false)]
+    tcp: bool,
+
+    /// Talk to a local station over its unix socket instead of UDP
+    #[arg(long, conflicts_with = "tcp")]
+    unix_socket: Option<String>,
+
+    /// Ask the station to read sensors now instead of returning cached samples
+    #[arg(long, global = true, default_value_t = false)]
+    fresh: bool,
+
+    /// Controller config file listing named stations
+    #[arg(long, default_value = "controller.json")]
+    config: String,
+
+    /// Send the command to every station in the controller config
+    #[arg(long, default_value_t = false, conflicts_with = "stations")]
+    all: bool,
+
+    /// Send the command to these stations from the controller config
+    #[arg(long, value_delimiter = ',')]
+    stations: Vec<String>,
+
+    /// File with the station's pre-shared key, requests are signed with it
+    #[arg(long)]
+    psk_file: Option<String>,
+
+    /// Print results of --all and --stations as JSON instead of a table
+    #[arg(long, default_value_t = false)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
 }
 
-fn build_get_status_req(status_type: StatusType) -> Vec<u8> {
-    let req = msg::ps::GetStatusReq::new(status_type);
-    println!("{:#?}", req);
-    let mut ret = vec![msg::MessageId::GetStatusReq as u8];
-    ret.append(bincode::serialize(&req).unwrap().as_mut());
-    ret
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Read station status: adc register, i2c buses or access counters
+    Status {
+        #[arg(long, default_value = "adc", value_parser = parse_status_type)]
+        kind: StatusType,
+    },
+    /// Read ADC value
+    Adc {
+        #[arg(long, default_value_t = false)]
+        converted: bool,
+    },
+    /// Read temperature
+    Temperature,
+    /// Read hygrometer humidity
+    Humidity,
+    /// Read several sensors in one request
+    Read {
+        /// Sensor names, e.g. temperature humidity:0 adc_mv:0
+        #[arg(long, num_args = 1.., default_value = "temperature")]
+        sensors: Vec<String>,
+    },
+    /// Scan the station I2C bus for responding devices
+    Scan {
+        /// I2C device path on the station, default bus if not given
+        #[arg(long, default_value = "")]
+        bus: String,
+        /// Multiplexer to open a channel on first, for devices placed behind it
+        #[arg(long, value_parser = parse_number::<u16>, requires = "channel")]
+        mux: Option<u16>,
+        #[arg(long, requires = "mux")]
+        channel: Option<u8>,
+    },
+    /// Read raw register bytes from an I2C device
+    ReadRegister {
+        /// I2C device path on the station, default bus if not given
+        #[arg(long, default_value = "")]
+        bus: String,
+        #[arg(long, value_parser = parse_number::<u16>)]
+        address: u16,
+        #[arg(long, default_value_t = false)]
+        ten_bit: bool,
+        /// Multiplexer to open a channel on first, for devices placed behind it
+        #[arg(long, value_parser = parse_number::<u16>, requires = "channel")]
+        mux: Option<u16>,
+        #[arg(long, requires = "mux")]
+        channel: Option<u8>,
+        #[arg(long, value_parser = parse_number::<u8>)]
+        register: u8,
+        #[arg(long, default_value_t = 1)]
+        length: u8,
+    },
+    /// Write raw register bytes to an I2C device
+    WriteRegister {
+        /// I2C device path on the station, default bus if not given
+        #[arg(long, default_value = "")]
+        bus: String,
+        #[arg(long, value_parser = parse_number::<u16>)]
+        address: u16,
+        #[arg(long, default_value_t = false)]
+        ten_bit: bool,
+        /// Multiplexer to open a channel on first, for devices placed behind it
+        #[arg(long, value_parser = parse_number::<u16>, requires = "channel")]
+        mux: Option<u16>,
+        #[arg(long, requires = "mux")]
+        channel: Option<u8>,
+        #[arg(long, value_parser = parse_number::<u8>)]
+        register: u8,
+        #[arg(long, num_args = 1.., value_parser = parse_number::<u8>)]
+        data: Vec<u8>,
+    },
+    /// Export aggregated sensor history from the station as CSV
+    History {
+        /// Sensor name, e.g. temperature, humidity:0, adc_mv:0
+        #[arg(long, default_value = "humidity:0")]
+        sensor: String,
+        /// Start of the range in RFC 3339, defaults to 24 hours ago
+        #[arg(long)]
+        from: Option<String>,
+        /// End of the range in RFC 3339, defaults to now
+        #[arg(long)]
+        to: Option<String>,
+        /// Bucket length in seconds
+        #[arg(long, default_value = "60")]
+        resolution: u32,
+        /// Output CSV file, stdout if not given
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// List stations answering on the local network
+    Discover {
+        /// Send to the multicast group instead of broadcasting
+        #[arg(long, default_value_t = false)]
+        multicast: bool,
+        /// Target address, e.g. a subnet broadcast address, overrides --multicast
+        #[arg(long)]
+        address: Option<String>,
+        #[arg(long, default_value_t = msg::DISCOVERY_PORT)]
+        port: u16,
+        /// Milliseconds to wait for answers
+        #[arg(long, default_value = "1000")]
+        timeout: u64,
+    },
+    /// Subscribe to sensors and log every reading the station pushes, UDP only
+    Watch {
+        /// Sensor names, e.g. temperature humidity:0 adc_mv:0
+        #[arg(long, num_args = 1.., default_value = "humidity:0")]
+        sensors: Vec<String>,
+        /// Seconds between checks on the station
+        #[arg(long, default_value = "1")]
+        interval: u32,
+        /// Only log values that changed at least this much, 0 logs every interval
+        #[arg(long, default_value = "0")]
+        threshold: u32,
+        /// Stop after this many seconds, runs until killed if not given
+        #[arg(long)]
+        duration: Option<u64>,
+        /// Output CSV file, stdout if not given
+        #[arg(long)]
+        output: Option<String>,
+    },
 }
 
-fn run_status(sock: &UdpSocket) {
-    let encoded = build_get_status_req(msg::ps::StatusType::ADC);
+fn parse_time(arg: &Option<String>, default: chrono::DateTime<chrono::Utc>) -> i64 {
+    match arg {
+        Some(time) => chrono::DateTime::parse_from_rfc3339(time)
+            .expect("Time must be in RFC 3339 format")
+            .timestamp_millis(),
+        None => default.timestamp_millis(),
+    }
+}
 
-    println!("{:?}", encoded);
-    let len = sock.send(encoded.as_slice()).unwrap();
-    println!("{:?} bytes sent", len);
-    let mut buf = [0; 1024];
-    let len = sock.recv(&mut buf).unwrap();
+fn parse_status_type(arg: &str) -> Result<StatusType, String> {
+    match arg.to_lowercase().as_str() {
+        "adc" => Ok(StatusType::ADC),
+        "i2c" => Ok(StatusType::I2C),
+        "access" => Ok(StatusType::Access),
+        _ => Err(String::from("Status kind must be adc, i2c or access")),
+    }
+}
 
-    // println!("{:?}", &buf[..len]);
-    let resp: msg::ps::GetStatusResp = bincode::deserialize(&buf[1..len]).unwrap();
-    println!("{:?}", resp);
+fn parse_number<T: TryFrom<u32>>(arg: &str) -> Result<T, String> {
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => arg.parse::<u32>(),
+    };
+    let value = parsed.map_err(|e| e.to_string())?;
+    T::try_from(value).map_err(|_| format!("{} out of range", arg))
+}
 
+fn read_psk(path: &str) -> Authenticator {
+    let psk = std::fs::read_to_string(path).expect("Failed to read pre-shared key file");
+    Authenticator::new(psk.trim().as_bytes(), msg::auth::DEFAULT_WINDOW_MS)
 }
 
-fn run_get_adc_value(sock: &UdpSocket, converted: bool) {
-    let req = msg::ps::GetAdcValueReq::new(converted, 0);
-    let mut encoded = vec![msg::MessageId::GetAdcValueReq as u8];
-    encoded.append(bincode::serialize(&req).unwrap().as_mut());
+fn run_status(client: &Client, kind: StatusType) {
+    match client.get_status(kind) {
+        Ok(status) => println!("{}", status),
+        Err(e) => println!("Status request failed: {}", e),
+    }
+}
 
-    println!("{:?}", encoded);
-    let len = sock.send(encoded.as_slice()).unwrap();
-    println!("{:?} bytes sent", len);
-    let mut buf = [0; 10];
-    let len = sock.recv(&mut buf).unwrap();
+fn run_get_adc_value(client: &Client, converted: bool, fresh: bool) {
+    match client.get_adc_value(converted, 0, fresh) {
+        Ok(resp) => println!("{:?}", resp),
+        Err(e) => println!("ADC request failed: {}", e),
+    }
+}
 
-    // println!("{:?}", &buf[..len]);
-    let resp: msg::ps::GetAdcValueResp = bincode::deserialize(&buf[1..len]).unwrap();
-    println!("{:?}", resp);
+fn run_get_higrometer_status(client: &Client, fresh: bool) {
+    match client.get_humidity(0, fresh) {
+        Ok(resp) => println!("{:?}", resp),
+        Err(e) => println!("Hygrometer request failed: {}", e),
+    }
 }
 
-fn run_get_higrometer_status(sock: &UdpSocket) -> Result<u8, String> {
-    let req = msg::ps::GetHygrometerStatusReq::new(0);
-    let mut encoded = vec![msg::MessageId::GetHygrometerStatusReq as u8];
-    encoded.append(bincode::serialize(&req).unwrap().as_mut());
+fn run_get_temperature(client: &Client, fresh: bool) {
+    match client.get_temperature(fresh) {
+        Ok(resp) => println!("{:?}", resp),
+        Err(e) => println!("Temperature request failed: {}", e),
+    }
+}
 
-    println!("{:?}", encoded);
-    let len = sock.send(encoded.as_slice()).unwrap();
-    println!("{:?} bytes sent", len);
-    let mut buf = [0; 10];
-    let len = sock.recv(&mut buf).unwrap();
+fn run_read_sensors(client: &Client, sensors: Vec<String>, fresh: bool) {
+    let readings = match client.read_sensors(sensors, fresh) {
+        Ok(readings) => readings,
+        Err(e) => {
+            println!("Read request failed: {}", e);
+            return;
+        }
+    };
+    for reading in readings {
+        if reading.error.is_empty() {
+            println!("{:<14} {:>8} ({} ms old)", reading.sensor, reading.value, reading.age_ms);
+        } else {
+            println!("{:<14} error: {}", reading.sensor, reading.error);
+        }
+    }
+}
 
-    if len == 0 {
-        return Err("No response received".to_string());
+fn mux_channel(mux: Option<u16>, channel: Option<u8>) -> Option<msg::ps::MuxChannel> {
+    mux.zip(channel).map(|(mux_address, channel)| msg::ps::MuxChannel::new(mux_address, channel))
+}
+
+fn run_scan_bus(client: &Client, bus: String, mux: Option<msg::ps::MuxChannel>) {
+    match client.scan_bus(&bus, mux) {
+        Ok(devices) => {
+            for device in devices {
+                println!("0x{:02x} {}", device.address, device.device);
+            }
+        }
+        Err(e) => println!("Scan failed: {}", e),
+    }
+}
+
+fn run_read_register(client: &Client, bus: String, address: u16, ten_bit: bool, mux: Option<msg::ps::MuxChannel>, register: u8, length: u8) {
+    match client.read_register(&bus, address, ten_bit, mux, register, length) {
+        Ok(data) => println!("0x{:02x}[0x{:02x}]: {:02x?}", address, register, data),
+        Err(e) => println!("Read failed: {}", e),
     }
-    else if len == 1 {
-        return Err("Error response received".to_string());
+}
+
+fn run_write_register(client: &Client, bus: String, address: u16, ten_bit: bool, mux: Option<msg::ps::MuxChannel>, register: u8, data: Vec<u8>) {
+    match client.write_register(&bus, address, ten_bit, mux, register, data) {
+        Ok(_) => println!("Write to 0x{:02x}[0x{:02x}] done", address, register),
+        Err(e) => println!("Write failed: {}", e),
     }
-    // println!("{:?}", &buf[..len]);
-    let resp: msg::ps::GetHygrometerStatusResp = bincode::deserialize(&buf[1..len]).unwrap();
-    println!("{:?}", resp);
-    Ok(resp.humidity)
 }
 
-fn run_get_temperature(sock: &UdpSocket) {
-    let req = msg::ps::GetTemperatureReq::new(0);
-    let mut encoded = vec![msg::MessageId::GetTemperatureReq as u8];
-    encoded.append(bincode::serialize(&req).unwrap().as_mut());
+fn run_get_history(client: &Client, sensor: String, from_ms: i64, to_ms: i64, resolution: u32, output: Option<String>) {
+    let buckets = match client.get_history(&sensor, from_ms, to_ms, resolution * 1000) {
+        Ok(buckets) => buckets,
+        Err(e) => {
+            println!("History request failed: {}", e);
+            return;
+        }
+    };
 
-    println!("{:?}", encoded);
-    let len = sock.send(encoded.as_slice()).unwrap();
-    println!("{:?} bytes sent", len);
-    let mut buf = [0; 10];
-    let len = sock.recv(&mut buf).unwrap();
+    // same layout as controller.log, so tools/plotter.py can read it
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).unwrap()),
+        None => Box::new(std::io::stdout()),
+    };
+    for bucket in buckets {
+        let timestamp = chrono::DateTime::from_timestamp_millis(bucket.start_ms).unwrap_or_default();
+        writeln!(out, "{}, {}, {}, {}", timestamp.to_rfc3339(), bucket.avg, bucket.min, bucket.max).unwrap();
+    }
+}
+
+fn send_subscription_msg<M: Message>(client: &Client, msg: &M) {
+    if let Err(e) = client.send(msg) {
+        println!("Sending subscription request failed: {}", e);
+    }
+}
 
-    if len == 0 {
-        println!("No response received");
+fn run_watch(client: &Client, sensors: Vec<String>, interval: u32, threshold: u32, duration: Option<u64>, output: Option<String>) {
+    const LEASE_MS: u32 = 30_000;
+    const RETRY_MS: u64 = 2_000;
+    const SUBSCRIBE_RESP: u8 = msg::MessageId::SubscribeResp as u8;
+    const READING_NOTIFICATION: u8 = msg::MessageId::ReadingNotification as u8;
+    let Some(udp) = client.udp() else {
+        println!("Watching needs UDP, the station pushes readings as datagrams");
         return;
+    };
+
+    // same layout as controller.log, so tools/plotter.py can read it
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(File::create(path).unwrap()),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut req = msg::ps::SubscribeReq::new(0, sensors, interval * 1000, threshold, LEASE_MS);
+    let stop_at = duration.map(|duration| Instant::now() + Duration::from_secs(duration));
+    let mut renew_at = Instant::now();
+    let mut confirmed = false;
+    loop {
+        let now = Instant::now();
+        if stop_at.is_some_and(|stop_at| now >= stop_at) {
+            break;
+        }
+        if now >= renew_at {
+            send_subscription_msg(client, &req);
+            // pushed back once the station confirms the lease
+            renew_at = now + Duration::from_millis(RETRY_MS);
+        }
+        let wait = renew_at.min(stop_at.unwrap_or(renew_at)).saturating_duration_since(now);
+        udp.set_read_timeout(Some(wait.max(Duration::from_millis(1)))).unwrap();
+        let buf = match client.recv_frame() {
+            Ok(buf) => buf,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                println!("Dropping datagram: {}", e);
+                continue;
+            }
+            Err(e) => {
+                println!("Receiving from station failed: {}", e);
+                return;
+            }
+        };
+        let len = buf.len();
+        if len <= 1 {
+            continue;
+        }
+        match buf[0] {
+            SUBSCRIBE_RESP => {
+                let resp: msg::ps::SubscribeResp = match codec::decode_payload(&buf[1..len]) {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                if !resp.error.is_empty() {
+                    println!("Subscription failed: {}", resp.error);
+                    if req.subscription_id == 0 {
+                        return;
+                    }
+                    // lease ran out or the station restarted, start over
+                    req.subscription_id = 0;
+                    confirmed = false;
+                    renew_at = Instant::now();
+                    continue;
+                }
+                let confirming = req.subscription_id != resp.subscription_id;
+                req.subscription_id = resp.subscription_id;
+                if confirming {
+                    // the station only pushes readings once the new id is echoed back
+                    renew_at = Instant::now();
+                    continue;
+                }
+                if !confirmed {
+                    println!("Subscribed as {} for {} ms", resp.subscription_id, resp.lease_ms);
+                    confirmed = true;
+                }
+                renew_at = Instant::now() + Duration::from_millis(resp.lease_ms as u64 / 2);
+            }
+            READING_NOTIFICATION => {
+                let notification: msg::ps::ReadingNotification = match codec::decode_payload(&buf[1..len]) {
+                    Ok(notification) => notification,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
+                    }
+                };
+                let timestamp = chrono::Utc::now().to_rfc3339();
+                for reading in notification.readings {
+                    if reading.error.is_empty() {
+                        writeln!(out, "{}, {}, {}", timestamp, reading.value, reading.sensor).unwrap();
+                    } else {
+                        println!("Reading {} failed: {}", reading.sensor, reading.error);
+                    }
+                }
+                out.flush().unwrap();
+            }
+            _ => (),
+        }
     }
-    else if len == 1 {
-        println!("Error response received");
+
+    if req.subscription_id == 0 {
         return;
     }
-    // println!("{:?}", &buf[..len]);
-    let resp: msg::ps::GetTemperatureResp = bincode::deserialize(&buf[1..len]).unwrap();
-    println!("{:?}", resp);
+    send_subscription_msg(client, &msg::ps::UnsubscribeReq::new(req.subscription_id));
+    udp.set_read_timeout(Some(Duration::from_millis(RETRY_MS))).unwrap();
+    // notifications already in flight may arrive before the response
+    while let Ok(buf) = client.recv_frame() {
+        if let Ok(resp) = codec::decode::<msg::ps::UnsubscribeResp>(&buf) {
+            if resp.error.is_empty() {
+                println!("Unsubscribed {}", req.subscription_id);
+            } else {
+                println!("Unsubscribing failed: {}", resp.error);
+            }
+            return;
+        }
+    }
+    println!("No response to unsubscribe, subscription {} expires with its lease", req.subscription_id);
+}
+
+/// With a key only stations sharing it answer, and only their signed responses are listed
+fn run_discover(multicast: bool, address: Option<String>, port: u16, timeout: u64, auth: Option<Authenticator>) -> std::io::Result<()> {
+    let target_ip: IpAddr = match address {
+        Some(address) => address.parse().expect("Invalid discovery address"),
+        None if multicast => msg::DISCOVERY_MULTICAST_ADDR.into(),
+        None => Ipv4Addr::BROADCAST.into(),
+    };
+    let sock = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0))?;
+    sock.set_broadcast(true)?;
+    let mut encoded = codec::encode(&msg::ps::DiscoverReq::new(msg::PROTOCOL_VERSION));
+    if let Some(auth) = &auth {
+        encoded = auth.seal(&encoded);
+    }
+    sock.send_to(encoded.as_slice(), SocketAddr::new(target_ip, port))?;
+
+    let deadline = Instant::now() + Duration::from_millis(timeout);
+    let mut buf = vec![0; 65536];
+    let mut found = 0;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        sock.set_read_timeout(Some(remaining))?;
+        let (len, src_addr) = match sock.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            Err(e) => return Err(e),
+        };
+        let frame = match &auth {
+            Some(auth) if len > 1 && buf[0] == msg::MessageId::Authenticated as u8 => match auth.open(&buf[1..len]) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    println!("Rejecting discovery response from {}: {}", src_addr, e);
+                    continue;
+                }
+            },
+            Some(_) => continue,
+            None => buf[..len].to_vec(),
+        };
+        if frame.len() <= 1 || frame[0] != msg::MessageId::DiscoverResp as u8 {
+            continue;
+        }
+        let resp: msg::ps::DiscoverResp = match codec::decode_payload(&frame[1..]) {
+            Ok(resp) => resp,
+            Err(e) => {
+                println!("Malformed discovery response from {}: {}", src_addr, e);
+                continue;
+            }
+        };
+        // stations bound to all interfaces are reached at the address they answered from
+        let ip = match resp.address.parse::<IpAddr>() {
+            Ok(ip) if !ip.is_unspecified() => ip,
+            _ => src_addr.ip(),
+        };
+        let version = if resp.protocol_version == msg::PROTOCOL_VERSION {
+            format!("v{}", resp.protocol_version)
+        } else {
+            format!("v{} (controller v{})", resp.protocol_version, msg::PROTOCOL_VERSION)
+        };
+        println!(
+            "{:<20} {:<34} {:<22} {:<8} {}",
+            resp.name, resp.id, SocketAddr::new(ip, resp.port), version, resp.capabilities.join(",")
+        );
+        found += 1;
+    }
+    println!("{} station(s) found", found);
+    Ok(())
 }
 
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
+    if let Command::Discover { multicast, address, port, timeout } = args.command {
+        return run_discover(multicast, address, port, timeout, args.psk_file.as_deref().map(read_psk));
+    }
+
+    if args.all || !args.stations.is_empty() {
+        let query = match args.command {
+            Command::Status { kind } => fleet::FleetQuery::Status { kind },
+            Command::Adc { converted } => fleet::FleetQuery::Adc { converted },
+            Command::Temperature => fleet::FleetQuery::Temperature,
+            Command::Humidity => fleet::FleetQuery::Humidity,
+            _ => {
+                println!("Only status, adc, temperature and humidity can be sent to several stations");
+                return Ok(());
+            }
+        };
+        match fleet::select_stations(fleet::load_stations(&args.config), &args.stations) {
+            Ok(stations) => fleet::run_fleet(stations, query, args.fresh, args.json),
+            Err(e) => println!("{}", e),
+        }
+        return Ok(());
+    }
 
     let ps_addr = SocketAddr::new(args.ps_ip.parse().unwrap(), args.ps_port);
     let ctrl_addr = SocketAddr::new(args.ctrl_addr.parse().unwrap(), args.ctrl_port);
-    let sock = UdpSocket::bind(ctrl_addr).expect("Failed to bind UDP socket");
-    sock.connect(ps_addr).expect("Failed to connect to UDP socket");
+    let client = if let Some(path) = &args.unix_socket {
+        Client::connect_unix(path)?
+    } else if args.tcp {
+        Client::connect_tcp(ps_addr, None)?
+    } else {
+        Client::connect_udp_from(ctrl_addr, ps_addr, None)?
+    };
+    let client = client.with_auth(args.psk_file.as_deref().map(read_psk));
     println!("Controller created on {}", ps_addr);
 
-    // run_get_adc_value(&sock, true);
-    run_get_temperature(&sock);
-    let _ = run_get_higrometer_status(&sock);
-
-    // let mut file = File::create("/home/admin/RustroverProjects/PlantStation/controller.log").unwrap();
-    // loop {
-    //     let humidity = run_get_higrometer_status(&sock).unwrap_or(0);
-    //     let timestamp = chrono::Utc::now().to_rfc3339();
-    //     // write humidity to file with newline
-    //     writeln!(file, "{}, {}",timestamp, humidity)?;
-    //     file.flush()?;
-    //     std::thread::sleep(std::time::Duration::from_secs(1));
-    // }
+    match args.command {
+        Command::Status { kind } => run_status(&client, kind),
+        Command::Adc { converted } => run_get_adc_value(&client, converted, args.fresh),
+        Command::Temperature => run_get_temperature(&client, args.fresh),
+        Command::Humidity => run_get_higrometer_status(&client, args.fresh),
+        Command::Read { sensors } => run_read_sensors(&client, sensors, args.fresh),
+        Command::Scan { bus, mux, channel } => run_scan_bus(&client, bus, mux_channel(mux, channel)),
+        Command::ReadRegister { bus, address, ten_bit, mux, channel, register, length } => {
+            run_read_register(&client, bus, address, ten_bit, mux_channel(mux, channel), register, length)
+        }
+        Command::WriteRegister { bus, address, ten_bit, mux, channel, register, data } => {
+            run_write_register(&client, bus, address, ten_bit, mux_channel(mux, channel), register, data)
+        }
+        Command::History { sensor, from, to, resolution, output } => {
+            let now = chrono::Utc::now();
+            let from_ms = parse_time(&from, now - chrono::Duration::hours(24));
+            let to_ms = parse_time(&to, now);
+            run_get_history(&client, sensor, from_ms, to_ms, resolution, output)
+        }
+        Command::Watch { sensors, interval, threshold, duration, output } => {
+            run_watch(&client, sensors, interval, threshold, duration, output)
+        }
+        Command::Discover { .. } => unreachable!("handled before connecting"),
+    }
     Ok(())
 }
@@ -0,0 +1,80 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use log::{error, info};
+use plantstation_protocol::msg::{self, MessageId};
+use plantstation_protocol::msg::codec;
+use plantstation_protocol::msg::ps::{DiscoverReq, DiscoverResp};
+use crate::station::Station;
+
+/// Answers discovery requests from sources the station's access control allows. Authenticated
+/// requests are answered with a signed response, unauthenticated ones only while the station
+/// does not require authentication. The socket is
+/// bound to all addresses because broadcasts never reach a socket bound to a unicast address,
+/// multicast is joined on the interface of the station address.
+pub fn spawn_discovery_responder(port: u16, station_ip: IpAddr, info: DiscoverResp, station: Station) -> io::Result<()> {
+    let sock = UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+    let interface = match station_ip {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+    };
+    // broadcasts arrive either way, multicast needs a route for the group
+    if let Err(e) = sock.join_multicast_v4(&msg::DISCOVERY_MULTICAST_ADDR, &interface) {
+        error!("Joining discovery multicast group {} failed: {}", msg::DISCOVERY_MULTICAST_ADDR, e);
+    }
+    let resp = codec::encode(&info);
+    info!("Answering discovery requests on port {} as {} ({})", port, info.name, info.id);
+
+    thread::spawn(move || {
+        let mut buf = [0; 256]; // room for an authenticated request
+        loop {
+            let (len, src_addr) = match sock.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Receiving discovery request failed: {}", e);
+                    continue;
+                }
+            };
+            if len == 0 || (buf[0] != MessageId::DiscoverReq as u8 && buf[0] != MessageId::Authenticated as u8) {
+                continue;
+            }
+            if let Err(rejection) = station.access().check(src_addr.ip()) {
+                info!("Ignoring discovery request from {}: {:?}", src_addr, rejection);
+                continue;
+            }
+            let auth = station.authenticator();
+            let (frame, authenticated) = match auth {
+                Some(auth) if buf[0] == MessageId::Authenticated as u8 => match auth.open(&buf[1..len]) {
+                    Ok(frame) => (frame, true),
+                    Err(e) => {
+                        info!("Ignoring discovery request from {}: {}", src_addr, e);
+                        continue;
+                    }
+                },
+                _ if station.auth_required() => {
+                    info!("Ignoring unauthenticated discovery request from {}", src_addr);
+                    continue;
+                }
+                _ => (buf[..len].to_vec(), false),
+            };
+            if frame[0] != MessageId::DiscoverReq as u8 {
+                continue;
+            }
+            match codec::decode_payload::<DiscoverReq>(&frame[1..]) {
+                Ok(req) => info!("Discovery request from {}, protocol version {}", src_addr, req.protocol_version),
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            }
+            let out = match auth {
+                Some(auth) if authenticated => auth.seal(&resp),
+                _ => resp.clone(),
+            };
+            if let Err(e) = sock.send_to(&out, src_addr) {
+                error!("Sending discovery response to {} failed: {}", src_addr, e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::thread;
+use std::time::Duration;
+use json::JsonValue;
+use plantstation_protocol::client::Client;
+use plantstation_protocol::msg::ps::StatusType;
+use plantstation_protocol::msg::auth::{self, Authenticator};
+
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+/// Station entry of the controller config file
+#[derive(Clone, Debug)]
+pub struct StationEntry {
+    pub name: String,
+    pub address: String, // host name or ip
+    pub port: u16,
+    pub tcp: bool,
+    pub timeout: Duration, // for connecting and for each response
+    pub psk: Option<String>, // requests are signed when set
+}
+
+/// Request sent to every selected station
+#[derive(Clone, Copy, Debug)]
+pub enum FleetQuery {
+    Status { kind: StatusType },
+    Adc { converted: bool },
+    Temperature,
+    Humidity,
+}
+
+/// Reads the station list, e.g.
+/// {"timeout_ms": 1000, "stations": [{"name": "greenhouse", "address": "192.168.1.20", "port": 8080, "tcp": false, "psk": "..."}]}
+pub fn load_stations(path: &str) -> Vec<StationEntry> {
+    let file = fs::read_to_string(path).expect("Failed to read controller config");
+    let parsed = json::parse(&file).expect("Controller config is not valid json");
+    let default_timeout = parsed["timeout_ms"].as_u64().unwrap_or(DEFAULT_TIMEOUT_MS);
+    parsed["stations"]
+        .members()
+        .map(|station| StationEntry {
+            name: station["name"].as_str().expect("Station name not defined").to_string(),
+            address: station["address"].as_str().expect("Station address not defined").to_string(),
+            port: station["port"].as_u16().unwrap_or(DEFAULT_PORT),
+            tcp: station["tcp"].as_bool().unwrap_or(false),
+            timeout: Duration::from_millis(station["timeout_ms"].as_u64().unwrap_or(default_timeout).max(1)),
+            psk: station["psk"].as_str().map(|psk| psk.to_string()),
+        })
+        .collect()
+}
+
+/// All stations, or the named ones in the given order
+pub fn select_stations(stations: Vec<StationEntry>, names: &[String]) -> Result<Vec<StationEntry>, String> {
+    if names.is_empty() {
+        return Ok(stations);
+    }
+    names
+        .iter()
+        .map(|name| {
+            stations
+                .iter()
+                .find(|station| &station.name == name)
+                .cloned()
+                .ok_or(format!("Station {} not found in controller config", name))
+        })
+        .collect()
+}
+
+fn connect(station: &StationEntry) -> Result<Client, String> {
+    let addr = (station.address.as_str(), station.port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or(format!("No address for {}", station.address))?;
+    let auth = station
+        .psk
+        .as_ref()
+        .map(|psk| Authenticator::new(psk.as_bytes(), auth::DEFAULT_WINDOW_MS));
+    let client = if station.tcp {
+        Client::connect_tcp(addr, Some(station.timeout))
+    } else {
+        Client::connect_udp(addr, Some(station.timeout))
+    };
+    Ok(client.map_err(|e| e.to_string())?.with_auth(auth))
+}
+
+fn query_station(station: &StationEntry, query: FleetQuery, fresh: bool) -> Result<JsonValue, String> {
+    let client = connect(station)?;
+    match query {
+        FleetQuery::Status { kind } => {
+            let status = client.get_status(kind)?;
+            Ok(json::object! { status: status })
+        }
+        FleetQuery::Adc { converted } => {
+            let resp = client.get_adc_value(converted, 0, fresh)?;
+            Ok(json::object! { value: resp.value, age_ms: resp.age_ms })
+        }
+        FleetQuery::Temperature => {
+            let resp = client.get_temperature(fresh)?;
+            Ok(json::object! { temperature: resp.temperature, age_ms: resp.age_ms })
+        }
+        FleetQuery::Humidity => {
+            let resp = client.get_humidity(0, fresh)?;
+            Ok(json::object! { humidity: resp.humidity, age_ms: resp.age_ms })
+        }
+    }
+}
+
+/// Queries all stations in parallel, so an offline station only costs its own timeout
+pub fn run_fleet(stations: Vec<StationEntry>, query: FleetQuery, fresh: bool, as_json: bool) {
+    let handles: Vec<_> = stations
+        .into_iter()
+        .map(|station| thread::spawn(move || {
+            let result = query_station(&station, query, fresh);
+            (station, result)
+        }))
+        .collect();
+    let results: Vec<(StationEntry, Result<JsonValue, String>)> =
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+    if as_json {
+        let mut out = JsonValue::new_array();
+        for (station, result) in results {
+            let mut entry = json::object! {
+                station: station.name,
+                address: format!("{}:{}", station.address, station.port),
+            };
+            match result {
+                Ok(fields) => {
+                    for (key, value) in fields.entries() {
+                        entry[key] = value.clone();
+                    }
+                }
+                Err(e) => entry["error"] = e.into(),
+            }
+            out.push(entry).unwrap();
+        }
+        println!("{}", out.pretty(2));
+        return;
+    }
+
+    println!("{:<20} {:<24} RESULT", "STATION", "ADDRESS");
+    for (station, result) in results {
+        let summary = match result {
+            Ok(fields) => fields
+                .entries()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<String>>()
+                .join(" "),
+            Err(e) => format!("error: {}", e),
+        };
+        println!("{:<20} {:<24} {}", station.name, format!("{}:{}", station.address, station.port), summary);
+    }
+}
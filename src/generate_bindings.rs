@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::Path;
+use clap::Parser;
+use serde_generate::{CodeGeneratorConfig, Encoding, SourceInstaller, cpp, python3};
+use serde_reflection::{Registry, Tracer, TracerConfig};
+use plantstation_protocol::msg::{self, MessageId};
+use plantstation_protocol::msg::ps::*;
+
+const MODULE_NAME: &str = "plantstation";
+
+const STATUS_TYPES: &[(&str, StatusType)] = &[
+    ("Unknown", StatusType::Unknown),
+    ("I2C", StatusType::I2C),
+    ("ADC", StatusType::ADC),
+    ("Access", StatusType::Access),
+];
+
+/// Generates Python and C++ encoders and decoders for the station messages
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Directory the python and cpp bindings are written to
+    #[arg(long, default_value = "tools/bindings")]
+    out_dir: String,
+}
+
+fn trace_messages() -> Registry {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    tracer.trace_simple_type::<GetStatusReq>().unwrap();
+    tracer.trace_simple_type::<GetStatusResp>().unwrap();
+    tracer.trace_simple_type::<GetAdcValueReq>().unwrap();
+    tracer.trace_simple_type::<GetAdcValueResp>().unwrap();
+    tracer.trace_simple_type::<GetHygrometerStatusReq>().unwrap();
+    tracer.trace_simple_type::<GetHygrometerStatusResp>().unwrap();
+    tracer.trace_simple_type::<GetTemperatureReq>().unwrap();
+    tracer.trace_simple_type::<GetTemperatureResp>().unwrap();
+    tracer.trace_simple_type::<ScanBusReq>().unwrap();
+    tracer.trace_simple_type::<ScanBusResp>().unwrap();
+    tracer.trace_simple_type::<ReadRegisterReq>().unwrap();
+    tracer.trace_simple_type::<ReadRegisterResp>().unwrap();
+    tracer.trace_simple_type::<WriteRegisterReq>().unwrap();
+    tracer.trace_simple_type::<WriteRegisterResp>().unwrap();
+    tracer.trace_simple_type::<GetHistoryReq>().unwrap();
+    tracer.trace_simple_type::<GetHistoryResp>().unwrap();
+    tracer.trace_simple_type::<SubscribeReq>().unwrap();
+    tracer.trace_simple_type::<SubscribeResp>().unwrap();
+    tracer.trace_simple_type::<UnsubscribeReq>().unwrap();
+    tracer.trace_simple_type::<UnsubscribeResp>().unwrap();
+    tracer.trace_simple_type::<ReadingNotification>().unwrap();
+    tracer.trace_simple_type::<DiscoverReq>().unwrap();
+    tracer.trace_simple_type::<DiscoverResp>().unwrap();
+    tracer.trace_simple_type::<ReadSensorsReq>().unwrap();
+    tracer.trace_simple_type::<ReadSensorsResp>().unwrap();
+    tracer.trace_simple_type::<msg::auth::AuthenticatedMsg>().unwrap();
+    tracer.registry().expect("Message types not fully traced")
+}
+
+// message ids and status types go on the wire as a single byte, not as serde enums
+fn python_protocol() -> String {
+    let mut out = String::from("# Generated by GenerateBindings, do not edit\n\nfrom enum import IntEnum\n\n");
+    out += &format!("PROTOCOL_VERSION = {}\n", msg::PROTOCOL_VERSION);
+    out += &format!("DISCOVERY_PORT = {}\n", msg::DISCOVERY_PORT);
+    out += &format!("DISCOVERY_MULTICAST_ADDR = \"{}\"\n\n\n", msg::DISCOVERY_MULTICAST_ADDR);
+    out += "class MessageId(IntEnum):\n";
+    for (id, name) in MessageId::NAMES.iter().enumerate() {
+        out += &format!("    {} = {}\n", name, id);
+    }
+    out += "\n\nclass StatusType(IntEnum):\n";
+    for (name, status_type) in STATUS_TYPES {
+        out += &format!("    {} = {}\n", name, *status_type as u8);
+    }
+    out
+}
+
+fn cpp_protocol() -> String {
+    let mut out = String::from("// Generated by GenerateBindings, do not edit\n#pragma once\n\n#include <cstdint>\n\n");
+    out += &format!("namespace {} {{\n\n", MODULE_NAME);
+    out += &format!("constexpr uint16_t PROTOCOL_VERSION = {};\n", msg::PROTOCOL_VERSION);
+    out += &format!("constexpr uint16_t DISCOVERY_PORT = {};\n", msg::DISCOVERY_PORT);
+    out += &format!("constexpr const char *DISCOVERY_MULTICAST_ADDR = \"{}\";\n\n", msg::DISCOVERY_MULTICAST_ADDR);
+    out += "enum class MessageId : uint8_t {\n";
+    for (id, name) in MessageId::NAMES.iter().enumerate() {
+        out += &format!("    {} = {},\n", name, id);
+    }
+    out += "};\n\nenum class StatusType : uint8_t {\n";
+    for (name, status_type) in STATUS_TYPES {
+        out += &format!("    {} = {},\n", name, *status_type as u8);
+    }
+    out += &format!("}};\n\n}} // end of namespace {}\n", MODULE_NAME);
+    out
+}
+
+fn install<I: SourceInstaller<Error = Box<dyn std::error::Error>>>(installer: I, registry: &Registry) {
+    let config = CodeGeneratorConfig::new(MODULE_NAME.to_string()).with_encodings(vec![Encoding::Bincode]);
+    installer.install_module(&config, registry).expect("Failed to write module");
+    installer.install_serde_runtime().expect("Failed to write serde runtime");
+    installer.install_bincode_runtime().expect("Failed to write bincode runtime");
+}
+
+fn main() {
+    let args = Args::parse();
+    let registry = trace_messages();
+    let out_dir = Path::new(&args.out_dir);
+
+    let python_dir = out_dir.join("python");
+    install(python3::Installer::new(python_dir.clone(), None), &registry);
+    let python_protocol_file = python_dir.join(MODULE_NAME).join("protocol.py");
+    fs::write(&python_protocol_file, python_protocol()).expect("Failed to write python protocol constants");
+    println!("Python bindings written to {}", python_dir.display());
+
+    let cpp_dir = out_dir.join("cpp");
+    install(cpp::Installer::new(cpp_dir.clone()), &registry);
+    fs::write(cpp_dir.join("protocol.hpp"), cpp_protocol()).expect("Failed to write C++ protocol constants");
+    println!("C++ bindings written to {}", cpp_dir.display());
+}
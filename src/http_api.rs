@@ -0,0 +1,184 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use chrono::Utc;
+use json::JsonValue;
+use log::{error, info};
+use tiny_http::{Header, Method, Request, Response, Server};
+use crate::access::Rejection;
+use crate::app_context::Sensor;
+use crate::metrics;
+use crate::sampler::Reading;
+use crate::station::Station;
+
+const HTTP_THREADS: usize = 2;
+
+fn decode_component(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| decode_component(value))
+}
+
+fn reading_to_json(sensor: Sensor, reading: &Reading) -> JsonValue {
+    let mut out = json::object! {
+        id: sensor.name(),
+        age_ms: reading.age_ms(),
+    };
+    match &reading.value {
+        Ok(value) => out["value"] = (*value).into(),
+        Err(e) => out["error"] = e.clone().into(),
+    }
+    out
+}
+
+fn get_sensors(station: &Station) -> Result<JsonValue, (u16, String)> {
+    let sensors: Vec<JsonValue> = station
+        .hw()
+        .sensors()
+        .into_iter()
+        .map(|sensor| reading_to_json(sensor, &station.read(sensor, false)))
+        .collect();
+    Ok(json::object! { sensors: sensors })
+}
+
+fn get_sensor(station: &Station, id: &str, query: &str) -> Result<JsonValue, (u16, String)> {
+    let sensor = Sensor::from_name(&decode_component(id)).ok_or((404, format!("Unknown sensor {}", id)))?;
+    let fresh = query_param(query, "fresh").is_some_and(|fresh| fresh == "true" || fresh == "1");
+    Ok(reading_to_json(sensor, &station.read(sensor, fresh)))
+}
+
+fn get_status(station: &Station) -> Result<JsonValue, (u16, String)> {
+    let hw = station.hw();
+    Ok(json::object! {
+        i2c: hw.i2c_status(),
+        adc: hw.adc_status(),
+        access: station.access().status(),
+    })
+}
+
+fn get_history(station: &Station, query: &str) -> Result<JsonValue, (u16, String)> {
+    let history = station.history().ok_or((404, String::from("History storage disabled in configuration")))?;
+    let param = |name: &str| -> Result<Option<i64>, (u16, String)> {
+        match query_param(query, name) {
+            Some(value) => value.parse().map(Some).map_err(|_| (400, format!("Invalid {} value", name))),
+            None => Ok(None),
+        }
+    };
+    let sensor_name = query_param(query, "sensor").ok_or((400, String::from("Missing sensor parameter")))?;
+    let sensor = Sensor::from_name(&sensor_name).ok_or((404, format!("Unknown sensor {}", sensor_name)))?;
+    let to_ms = param("to")?.unwrap_or_else(|| Utc::now().timestamp_millis());
+    let from_ms = param("from")?.unwrap_or(to_ms.saturating_sub(24 * 60 * 60 * 1000).max(0));
+    let resolution_ms = param("resolution")?.unwrap_or(60 * 1000);
+
+    let buckets: Vec<JsonValue> = history
+        .aggregate(sensor, from_ms, to_ms, resolution_ms)
+        .map_err(|e| (400, e))?
+        .iter()
+        .map(|bucket| {
+            json::object! {
+                start_ms: bucket.start_ms,
+                min: bucket.min,
+                max: bucket.max,
+                avg: bucket.avg,
+                count: bucket.count,
+            }
+        })
+        .collect();
+    Ok(json::object! { sensor: sensor.name(), buckets: buckets })
+}
+
+fn handle_request(request: Request, station: &Station) {
+    info!("HTTP {} {}", request.method(), request.url());
+    let access = match request.remote_addr() {
+        Some(addr) => station.access().check(addr.ip()),
+        None => Ok(()),
+    };
+    if let Err(rejection) = access {
+        let status = match rejection {
+            Rejection::Denied => 403,
+            Rejection::RateLimited => 429,
+        };
+        if let Err(e) = request.respond(Response::empty(status)) {
+            error!("Sending HTTP response failed: {}", e);
+        }
+        return;
+    }
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+
+    if request.method() == &Method::Get && segments.as_slice() == ["metrics"] {
+        let content_type = Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+        let response = Response::from_string(metrics::render(station)).with_header(content_type);
+        if let Err(e) = request.respond(response) {
+            error!("Sending metrics failed: {}", e);
+        }
+        return;
+    }
+
+    let result = match (request.method(), segments.as_slice()) {
+        (Method::Get, ["sensors"]) => get_sensors(station),
+        (Method::Get, ["sensors", id]) => get_sensor(station, id, &query),
+        (Method::Get, ["status"]) => get_status(station),
+        (Method::Get, ["history"]) => get_history(station, &query),
+        (Method::Get, _) => Err((404, String::from("Not found"))),
+        _ => Err((405, String::from("Method not allowed"))),
+    };
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err((status, e)) => (status, json::object! { error: e }),
+    };
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    let response = Response::from_string(body.dump())
+        .with_status_code(status)
+        .with_header(content_type);
+    if let Err(e) = request.respond(response) {
+        error!("Sending HTTP response failed: {}", e);
+    }
+}
+
+/// Read-only JSON view of the station for browsers and dashboards
+pub fn spawn_http_server(addr: SocketAddr, station: Station) -> Result<(), String> {
+    let server = Arc::new(Server::http(addr).map_err(|e| e.to_string())?);
+    info!("Serving HTTP API on {}", addr);
+    for _ in 0..HTTP_THREADS {
+        let server = server.clone();
+        let station = station.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle_request(request, &station);
+            }
+        });
+    }
+    Ok(())
+}
@@ -1,56 +1,171 @@
-use crate::app_context::{AdcSupported, AppContext};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::Ordering;
+use crate::app_context::{AdcConfig, AppContext, DeviceData, I2cTraceMode, MuxRoute, Sensor};
+use crate::hw::i2c_bus::{Bus, LinuxBus, ReplayBus, TraceBus, TraceWriter};
 use crate::hw::i2c_mgmt::I2cDevice;
+pub use crate::hw::i2c_mgmt::{I2cCounters, I2cTarget};
 use String;
 
+mod i2c_bus;
 mod i2c_mgmt;
 mod adc;
 mod thermometer;
 
 pub struct Hw {
     app_context: AppContext,
-    i2c: I2cDevice,
-    adc: Box<dyn adc::Adc>,
+    buses: BTreeMap<String, Mutex<I2cDevice>>, // locked per bus, so devices on other buses are not blocked
+    counters: BTreeMap<String, Arc<I2cCounters>>,
+    functionality: BTreeMap<String, String>, // read once when the bus is opened
+    adcs: Vec<Box<dyn adc::Adc>>, // in the order of AppContext::adcs
     thermometer: Box<dyn thermometer::Thermometer>,
 }
 
+fn open_bus(path: &str, trace_mode: &I2cTraceMode, trace_writer: &Option<TraceWriter>) -> Box<dyn Bus> {
+    if let I2cTraceMode::Replay(trace_path) = trace_mode {
+        return Box::new(ReplayBus::new(path, trace_path).expect("Failed to load I2C trace"));
+    }
+    let bus = Box::new(LinuxBus::new(path).expect("Failed to create i2c device"));
+    match trace_writer {
+        Some(writer) => Box::new(TraceBus::new(path, bus, writer.clone())),
+        None => bus,
+    }
+}
+
 impl Hw {
     pub fn new(context: AppContext) -> Hw {
-        if context.adc_config.adc_type == AdcSupported::Unknown {
-            panic!("Unsupported ADC type in configuration");
-        }
+        let trace_writer = match &context.i2c_trace {
+            I2cTraceMode::Record(trace_path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(trace_path)
+                    .expect("Failed to open I2C trace file");
+                Some(Arc::new(Mutex::new(file)))
+            }
+            _ => None,
+        };
+        let buses: BTreeMap<String, Mutex<I2cDevice>> = context
+            .i2c_buses()
+            .into_iter()
+            .map(|path| {
+                let bus = open_bus(&path, &context.i2c_trace, &trace_writer);
+                let muxes = context.muxes.iter().filter(|mux| mux.bus == path).map(|mux| mux.address).collect();
+                (path.clone(), Mutex::new(I2cDevice::new(path, bus, muxes, context.i2c_recovery)))
+            })
+            .collect();
+        let counters = buses
+            .iter()
+            .map(|(path, i2c)| (path.clone(), i2c.lock().unwrap().counters()))
+            .collect();
+        let functionality = buses
+            .iter()
+            .map(|(path, i2c)| {
+                let functionality = i2c.lock().unwrap().functionality().unwrap_or_else(|error| error);
+                (path.clone(), functionality)
+            })
+            .collect();
         Hw {
             app_context: context.clone(),
-            i2c: I2cDevice::new(context.i2c_dev_path),
-            adc: Box::new(adc::Ads1115::new( // todo: support other ADCs
-                context.adc_config.adc_address,
-                context.adc_config.registers_values.clone(),
-            )),
+            buses,
+            counters,
+            functionality,
+            adcs: context
+                .adcs
+                .iter()
+                .map(|adc_config| -> Box<dyn adc::Adc> {
+                    Box::new(adc::Ads1115::new( // todo: support other ADCs
+                        I2cTarget::from(&adc_config.device_data),
+                        adc_config.device_data.registers_values.clone(),
+                    ))
+                })
+                .collect(),
             thermometer: Box::new(thermometer::Lps331ap::new( // todo: support other thermometers
-                context.thermometer_config.device_data.address,
+                I2cTarget::from(&context.thermometer_config.device_data),
                 context.thermometer_config.device_data.registers_values.clone(),
             )),
         }
     }
 
-    pub fn initialize(&mut self) -> Result<(), String> {
+    fn bus(&self, path: &str) -> Result<MutexGuard<'_, I2cDevice>, String> {
+        match self.buses.get(path) {
+            // a request that panicked mid transaction leaves the bus usable, transactions select
+            // their slave and multiplexer channels again anyway
+            Some(i2c) => Ok(i2c.lock().unwrap_or_else(PoisonError::into_inner)),
+            None => Err(format!("I2C bus {} not configured", path)),
+        }
+    }
+
+    // empty bus path in requests selects the default bus from configuration
+    fn bus_path(&self, bus: &str) -> String {
+        if bus.is_empty() {
+            return self.app_context.i2c_dev_path.clone();
+        }
+        bus.to_string()
+    }
+
+    /// Locks the thermometer bus, re-running the thermometer initialization first if the bus was
+    /// reopened after being stuck. ADCs are configured on every read and need no initialization.
+    fn thermometer_bus(&self) -> Result<MutexGuard<'_, I2cDevice>, String> {
+        let path = &self.app_context.thermometer_config.device_data.bus;
+        let mut i2c = self.bus(path)?;
+        if i2c.take_recovered() {
+            println!("Reinitializing thermometer on recovered bus {}", path);
+            if let Err(e) = self.thermometer.initialize(&mut i2c) {
+                println!("Thermometer reinitialization failed: {}", e);
+            }
+        }
+        Ok(i2c)
+    }
+
+    pub fn initialize(&self) -> Result<(), String> {
         println!("Initializing hardware components");
-        match self.thermometer.initialize(&mut self.i2c) {
+        let mut i2c = self.bus(&self.app_context.thermometer_config.device_data.bus)?;
+        match self.thermometer.initialize(&mut i2c) {
             Ok(_) => Ok(()),
             Err(e) => Err(format!("Thermometer initialization failed: {}", e)),
         }
     }
     
+    /// Built from the functionality read at startup and the counters, so it never waits for a bus lock
     pub fn i2c_status(&self) -> String {
-        match self.i2c.functionality() {
-            Ok(functionality) => format!("{} | {}", self.i2c.dev_path(), functionality),
-            Err(error) => {
-                error.to_string()
-            }
-        }
+        let statuses: Vec<String> = self
+            .counters
+            .iter()
+            .map(|(path, counters)| {
+                format!(
+                    "{} | {} | errors: {}, retries: {}, recoveries: {}",
+                    path,
+                    self.functionality[path],
+                    counters.errors.load(Ordering::Relaxed),
+                    counters.retries.load(Ordering::Relaxed),
+                    counters.recoveries.load(Ordering::Relaxed)
+                )
+            })
+            .collect();
+        statuses.join("\n")
     }
 
-    pub fn adc_status(&mut self) -> String {
-        let adc_conf = &self.app_context.adc_config;
+    /// Counters of every bus, read without taking the bus locks
+    pub fn i2c_counters(&self) -> &BTreeMap<String, Arc<I2cCounters>> {
+        &self.counters
+    }
+
+    pub fn adc_status(&self) -> String {
+        let adcs = &self.app_context.adcs;
+        let statuses: Vec<String> = adcs
+            .iter()
+            .map(|adc_config| match adcs.len() {
+                1 => self.read_adc_status(adc_config),
+                _ => format!("{:x}: {}", adc_config.device_data.address, self.read_adc_status(adc_config)),
+            })
+            .collect();
+        statuses.join("\n")
+    }
+
+    fn read_adc_status(&self, adc_config: &AdcConfig) -> String {
+        let adc_conf = &adc_config.device_data;
         const REG_LEN: usize = 2;
 
         if !adc_conf.registers.contains_key("Config") {
@@ -58,11 +173,11 @@ impl Hw {
             return String::from("Error: ADC Config register not defined");
         }
 
-        println!("Retrieving ADC status from address {:x}", adc_conf.adc_address);
+        println!("Retrieving ADC status from address {:x}", adc_conf.address);
 
         match self
-            .i2c
-            .get_register(adc_conf.adc_address, adc_conf.registers["Config"], REG_LEN)
+            .bus(&adc_conf.bus)
+            .and_then(|mut i2c| i2c.get_register(&I2cTarget::from(adc_conf), adc_conf.registers["Config"], REG_LEN))
         {
             Ok(status_reg) => {
                 let mut status_hex = String::from("0x");
@@ -78,14 +193,19 @@ impl Hw {
             }
         }
     }
-    
-    pub fn read_adc_value(&mut self, converted: bool, channel: u8) -> Result<u16, String> {
-        let raw_bytes_result = self.adc.read_val(&mut self.i2c, channel);
+
+    pub fn read_adc_value(&self, converted: bool, channel: u8) -> Result<u16, String> {
+        let Some((index, adc_channel)) = self.app_context.adc_for_channel(channel) else {
+            return Err(format!("ADC channel {} not configured", channel));
+        };
+        let adc = &self.adcs[index];
+        let mut i2c = self.bus(&self.app_context.adcs[index].device_data.bus)?;
+        let raw_bytes_result = adc.read_val(&mut i2c, adc_channel);
         match raw_bytes_result {
             Ok(raw_bytes) => {
                 let raw = u16::from_be_bytes([raw_bytes[0], raw_bytes[1]]);
                 if converted {
-                    return Ok(self.adc.raw_to_voltage(raw));
+                    return Ok(adc.raw_to_voltage(raw));
                 }
                 Ok(raw)
             }
@@ -95,7 +215,7 @@ impl Hw {
         }
     }
 
-    pub fn read_humidity(&mut self, channel: u8) -> Result<u8, String> {
+    pub fn read_humidity(&self, channel: u8) -> Result<u8, String> {
         let voltage = self.read_adc_value(true, channel);
         match voltage {
             Ok(voltage_mv) => {
@@ -108,7 +228,118 @@ impl Hw {
         }
     }
     
-    pub fn read_temperature(&mut self) -> Result<i16, String> {
-        self.thermometer.read_temperature(&mut self.i2c)
+    pub fn read_temperature(&self) -> Result<i16, String> {
+        let mut i2c = self.thermometer_bus()?;
+        self.thermometer.read_temperature(&mut i2c)
+    }
+
+    pub fn read_pressure(&self) -> Result<u16, String> {
+        let mut i2c = self.thermometer_bus()?;
+        self.thermometer.read_pressure(&mut i2c)
+    }
+
+    pub fn sensors(&self) -> Vec<Sensor> {
+        self.app_context.sensors()
+    }
+
+    pub fn hardware_model(&self) -> String {
+        self.app_context.hardware_model()
+    }
+
+    pub fn read_sensor(&self, sensor: Sensor) -> Result<i32, String> {
+        match sensor {
+            Sensor::Temperature => self.read_temperature().map(i32::from),
+            Sensor::Pressure => self.read_pressure().map(i32::from),
+            Sensor::Humidity(channel) => self.read_humidity(channel).map(i32::from),
+            Sensor::AdcRaw(channel) => self.read_adc_value(false, channel).map(i32::from),
+            Sensor::AdcMillivolts(channel) => self.read_adc_value(true, channel).map(i32::from),
+        }
+    }
+
+    // routes in requests must name a configured multiplexer and one of its channels
+    fn check_mux_route(&self, bus: &str, route: &MuxRoute) -> Result<(), String> {
+        match self.app_context.muxes.iter().find(|mux| mux.bus == bus && mux.address == route.mux_address) {
+            Some(mux) if route.channel < mux.channels => Ok(()),
+            Some(_) => Err(format!("Channel {} not available on multiplexer {:#x}", route.channel, route.mux_address)),
+            None => Err(format!("Multiplexer {:#x} on {} not configured", route.mux_address, bus)),
+        }
+    }
+
+    pub fn read_register(&self, bus: &str, target: I2cTarget, register: u8, length: u8) -> Result<Vec<u8>, String> {
+        const MAX_LEN: u8 = 4;
+        if !self.app_context.register_access {
+            return Err(String::from("Register access disabled in configuration"));
+        }
+        let bus = self.bus_path(bus);
+        if let Some(route) = &target.mux {
+            self.check_mux_route(&bus, route)?;
+        }
+        let mut i2c = self.bus(&bus)?;
+        match length {
+            1..=MAX_LEN => i2c.debug_read_register(&target, register, length as usize),
+            _ => Err(format!("Register length must be between 1 and {}", MAX_LEN)),
+        }
+    }
+
+    pub fn write_register(&self, bus: &str, target: I2cTarget, register: u8, data: &Vec<u8>) -> Result<(), String> {
+        if !self.app_context.register_access {
+            return Err(String::from("Register access disabled in configuration"));
+        }
+        if data.is_empty() {
+            return Err(String::from("No data to write"));
+        }
+        let bus = self.bus_path(bus);
+        if let Some(route) = &target.mux {
+            self.check_mux_route(&bus, route)?;
+        }
+        self.bus(&bus)?.debug_write_register(&target, register, data)
+    }
+
+    pub fn scan_bus(&self, bus: &str, mux: Option<MuxRoute>) -> Result<Vec<(u16, String)>, String> {
+        const FIRST_ADDRESS: u16 = 0x03;
+        const LAST_ADDRESS: u16 = 0x77;
+        let bus = self.bus_path(bus);
+        if let Some(route) = &mux {
+            self.check_mux_route(&bus, route)?;
+        }
+        let mut i2c = self.bus(&bus)?;
+        println!("Scanning {} for devices", i2c.dev_path());
+        i2c.prepare_scan(mux.as_ref())?;
+
+        // devices behind a multiplexer are only visible when their channel is selected
+        let configured: Vec<(&str, &DeviceData)> = self
+            .app_context
+            .adcs
+            .iter()
+            .map(|adc_config| ("adc", &adc_config.device_data))
+            .chain([("thermometer", &self.app_context.thermometer_config.device_data)])
+            .collect();
+        let muxes = &self.app_context.muxes;
+        let mut found = Vec::new();
+        for address in FIRST_ADDRESS..=LAST_ADDRESS {
+            if !i2c.probe(address) {
+                continue;
+            }
+            let name = configured
+                .iter()
+                .filter(|(_, device)| {
+                    device.bus == bus
+                        && !device.ten_bit_address
+                        && (device.mux.is_none() || device.mux == mux) // the parent bus stays visible
+                        && device.address == address
+                })
+                .map(|(name, _)| name.to_string())
+                .next()
+                .or_else(|| {
+                    muxes
+                        .iter()
+                        .find(|mux| mux.bus == bus && mux.address == address)
+                        .map(|mux| format!("{:?} mux", mux.mux_type))
+                })
+                .unwrap_or_default();
+            println!("Device found at {:x}: {}", address, name);
+            found.push((address, name));
+        }
+        Ok(found)
     }
 }
@@ -1,15 +1,15 @@
 use std::collections::HashMap;
-use crate::hw::i2c_mgmt::I2cDevice;
+use crate::hw::i2c_mgmt::{I2cDevice, I2cTarget};
 
 use bit_vec::BitVec;
 
-pub trait Adc {
+pub trait Adc: Send + Sync {
     fn read_val(&self, i2c: &mut I2cDevice, channel: u8) -> Result<Vec<u8>, String>;
     fn raw_to_voltage(&self, raw_val: u16) -> u16;
 }
 
 pub struct Ads1115 {
-    address: u16,
+    target: I2cTarget,
     config_value: Vec<u8>,
 }
 
@@ -35,13 +35,13 @@ fn config_for_mux(channel: u8, base_config: &Vec<u8>) -> Vec<u8> {
 impl Ads1115 {
     const CONFIG_REGISTER: u8 = 0x01;
     const CONVERSION_REGISTER: u8 = 0x00;
-    pub fn new(addr: u16, init_config: HashMap<String, Vec<u8>>) -> Self {
+    pub fn new(target: I2cTarget, init_config: HashMap<String, Vec<u8>>) -> Self {
         let config = init_config.get("Config").expect("Config register not found in init_config");
         if config.len() != 2 {
             panic!("Config value must be 2 bytes for ADS1115");
         }
         Ads1115 {
-            address: addr,
+            target,
             config_value: config.clone(),
         }
     }
@@ -54,11 +54,11 @@ impl Adc for Ads1115 {
         }
         println!("Reading from ADS1115");
         let config_value = config_for_mux(channel, &self.config_value);
-        match i2c.write_register(self.address, Self::CONFIG_REGISTER, config_value.as_ref()) {
+        match i2c.write_register(&self.target, Self::CONFIG_REGISTER, config_value.as_ref()) {
             Ok(_) => (),
             Err(e) => return Err(format!("Failed to write config to ADS1115: {}", e)),
         }
-        i2c.get_register(self.address, Self::CONVERSION_REGISTER, 2)
+        i2c.get_register(&self.target, Self::CONVERSION_REGISTER, 2)
     }
 
     fn raw_to_voltage(&self, raw_val: u16) -> u16 {
@@ -0,0 +1,461 @@
+use i2c_linux::{I2c, ReadWrite};
+use json::JsonValue;
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Raw bus operations used by I2cDevice, so the linux device can be wrapped or replaced
+pub trait Bus: Send {
+    fn reopen(&mut self) -> io::Result<()>;
+    fn i2c_functionality(&self) -> io::Result<String>;
+    fn smbus_set_slave_address(&mut self, address: u16, tenbit: bool) -> io::Result<()>;
+    fn smbus_set_pec(&mut self, pec: bool) -> io::Result<()>;
+    fn smbus_write_quick(&mut self) -> io::Result<()>;
+    fn smbus_read_byte(&mut self) -> io::Result<u8>;
+    fn smbus_write_byte(&mut self, value: u8) -> io::Result<()>;
+    fn smbus_read_byte_data(&mut self, register: u8) -> io::Result<u8>;
+    fn smbus_write_byte_data(&mut self, register: u8, value: u8) -> io::Result<()>;
+    fn smbus_read_word_data(&mut self, register: u8) -> io::Result<u16>;
+    fn smbus_write_word_data(&mut self, register: u8, value: u16) -> io::Result<()>;
+    fn i2c_read_block_data(&mut self, register: u8, value: &mut [u8]) -> io::Result<usize>;
+    fn i2c_write_block_data(&mut self, register: u8, value: &[u8]) -> io::Result<()>;
+}
+
+pub struct LinuxBus {
+    dev_path: String,
+    dev: I2c<File>,
+}
+
+impl LinuxBus {
+    pub fn new(dev_path: &str) -> io::Result<LinuxBus> {
+        Ok(LinuxBus {
+            dev_path: dev_path.to_string(),
+            dev: I2c::from_path(dev_path)?,
+        })
+    }
+}
+
+impl Bus for LinuxBus {
+    fn reopen(&mut self) -> io::Result<()> {
+        self.dev = I2c::from_path(&self.dev_path)?;
+        Ok(())
+    }
+
+    fn i2c_functionality(&self) -> io::Result<String> {
+        self.dev.i2c_functionality().map(|functionality| format!("{:?}", functionality))
+    }
+
+    fn smbus_set_slave_address(&mut self, address: u16, tenbit: bool) -> io::Result<()> {
+        self.dev.smbus_set_slave_address(address, tenbit)
+    }
+
+    fn smbus_set_pec(&mut self, pec: bool) -> io::Result<()> {
+        self.dev.smbus_set_pec(pec)
+    }
+
+    fn smbus_write_quick(&mut self) -> io::Result<()> {
+        self.dev.smbus_write_quick(ReadWrite::Write)
+    }
+
+    fn smbus_read_byte(&mut self) -> io::Result<u8> {
+        self.dev.smbus_read_byte()
+    }
+
+    fn smbus_write_byte(&mut self, value: u8) -> io::Result<()> {
+        self.dev.smbus_write_byte(value)
+    }
+
+    fn smbus_read_byte_data(&mut self, register: u8) -> io::Result<u8> {
+        self.dev.smbus_read_byte_data(register)
+    }
+
+    fn smbus_write_byte_data(&mut self, register: u8, value: u8) -> io::Result<()> {
+        self.dev.smbus_write_byte_data(register, value)
+    }
+
+    fn smbus_read_word_data(&mut self, register: u8) -> io::Result<u16> {
+        self.dev.smbus_read_word_data(register)
+    }
+
+    fn smbus_write_word_data(&mut self, register: u8, value: u16) -> io::Result<()> {
+        self.dev.smbus_write_word_data(register, value)
+    }
+
+    fn i2c_read_block_data(&mut self, register: u8, value: &mut [u8]) -> io::Result<usize> {
+        self.dev.i2c_read_block_data(register, value)
+    }
+
+    fn i2c_write_block_data(&mut self, register: u8, value: &[u8]) -> io::Result<()> {
+        self.dev.i2c_write_block_data(register, value)
+    }
+}
+
+/// Single bus transaction, stored as one json object per line in trace files
+#[derive(Clone, Debug)]
+struct TraceRecord {
+    bus: String,
+    op: String,
+    address: u16,
+    register: Option<u8>,
+    bytes: Vec<u8>,
+    error: Option<String>,
+}
+
+impl TraceRecord {
+    fn to_json(&self, timestamp: String) -> JsonValue {
+        json::object! {
+            timestamp: timestamp,
+            bus: self.bus.clone(),
+            op: self.op.clone(),
+            address: self.address,
+            register: self.register,
+            bytes: self.bytes.clone(),
+            error: self.error.clone(),
+        }
+    }
+
+    fn from_json(value: &JsonValue) -> Option<TraceRecord> {
+        Some(TraceRecord {
+            bus: value["bus"].as_str()?.to_string(),
+            op: value["op"].as_str()?.to_string(),
+            address: value["address"].as_u16()?,
+            register: value["register"].as_u8(),
+            bytes: value["bytes"].members().filter_map(|byte| byte.as_u8()).collect(),
+            error: value["error"].as_str().map(|error| error.to_string()),
+        })
+    }
+}
+
+pub type TraceWriter = Arc<Mutex<File>>;
+
+/// Passes every operation to the wrapped bus and appends it to the trace file
+pub struct TraceBus {
+    dev_path: String,
+    inner: Box<dyn Bus>,
+    writer: TraceWriter,
+    address: u16,
+}
+
+impl TraceBus {
+    pub fn new(dev_path: &str, inner: Box<dyn Bus>, writer: TraceWriter) -> TraceBus {
+        TraceBus {
+            dev_path: dev_path.to_string(),
+            inner,
+            writer,
+            address: 0,
+        }
+    }
+
+    fn record<T>(&self, op: &str, register: Option<u8>, bytes: &[u8], result: &io::Result<T>) {
+        let record = TraceRecord {
+            bus: self.dev_path.clone(),
+            op: op.to_string(),
+            address: self.address,
+            register,
+            bytes: bytes.to_vec(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        };
+        let line = record.to_json(chrono::Utc::now().to_rfc3339()).dump();
+        match self.writer.lock() {
+            Ok(mut file) => {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    println!("Writing I2C trace failed: {}", e);
+                }
+            }
+            Err(_) => println!("I2C trace file lock poisoned"),
+        }
+    }
+}
+
+impl Bus for TraceBus {
+    fn reopen(&mut self) -> io::Result<()> {
+        let result = self.inner.reopen();
+        self.record("reopen", None, &[], &result);
+        result
+    }
+
+    fn i2c_functionality(&self) -> io::Result<String> {
+        self.inner.i2c_functionality()
+    }
+
+    fn smbus_set_slave_address(&mut self, address: u16, tenbit: bool) -> io::Result<()> {
+        self.address = address;
+        let result = self.inner.smbus_set_slave_address(address, tenbit);
+        self.record("set_slave_address", None, &[tenbit as u8], &result);
+        result
+    }
+
+    fn smbus_set_pec(&mut self, pec: bool) -> io::Result<()> {
+        let result = self.inner.smbus_set_pec(pec);
+        self.record("set_pec", None, &[pec as u8], &result);
+        result
+    }
+
+    fn smbus_write_quick(&mut self) -> io::Result<()> {
+        let result = self.inner.smbus_write_quick();
+        self.record("write_quick", None, &[], &result);
+        result
+    }
+
+    fn smbus_read_byte(&mut self) -> io::Result<u8> {
+        let result = self.inner.smbus_read_byte();
+        let bytes: Vec<u8> = result.as_ref().map(|byte| vec![*byte]).unwrap_or_default();
+        self.record("read_byte", None, &bytes, &result);
+        result
+    }
+
+    fn smbus_write_byte(&mut self, value: u8) -> io::Result<()> {
+        let result = self.inner.smbus_write_byte(value);
+        self.record("write_byte", None, &[value], &result);
+        result
+    }
+
+    fn smbus_read_byte_data(&mut self, register: u8) -> io::Result<u8> {
+        let result = self.inner.smbus_read_byte_data(register);
+        let bytes: Vec<u8> = result.as_ref().map(|byte| vec![*byte]).unwrap_or_default();
+        self.record("read_byte_data", Some(register), &bytes, &result);
+        result
+    }
+
+    fn smbus_write_byte_data(&mut self, register: u8, value: u8) -> io::Result<()> {
+        let result = self.inner.smbus_write_byte_data(register, value);
+        self.record("write_byte_data", Some(register), &[value], &result);
+        result
+    }
+
+    // words are recorded in bus order, low byte first
+    fn smbus_read_word_data(&mut self, register: u8) -> io::Result<u16> {
+        let result = self.inner.smbus_read_word_data(register);
+        let bytes: Vec<u8> = result.as_ref().map(|word| word.to_le_bytes().to_vec()).unwrap_or_default();
+        self.record("read_word_data", Some(register), &bytes, &result);
+        result
+    }
+
+    fn smbus_write_word_data(&mut self, register: u8, value: u16) -> io::Result<()> {
+        let result = self.inner.smbus_write_word_data(register, value);
+        self.record("write_word_data", Some(register), &value.to_le_bytes(), &result);
+        result
+    }
+
+    fn i2c_read_block_data(&mut self, register: u8, value: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.i2c_read_block_data(register, value);
+        let size = *result.as_ref().unwrap_or(&0);
+        self.record("read_block_data", Some(register), &value[..size], &result);
+        result
+    }
+
+    fn i2c_write_block_data(&mut self, register: u8, value: &[u8]) -> io::Result<()> {
+        let result = self.inner.i2c_write_block_data(register, value);
+        self.record("write_block_data", Some(register), value, &result);
+        result
+    }
+}
+
+/// Feeds transactions recorded by TraceBus back in order, failing on any divergence
+pub struct ReplayBus {
+    dev_path: String,
+    records: VecDeque<TraceRecord>,
+    address: u16,
+}
+
+impl ReplayBus {
+    pub fn new(dev_path: &str, trace_path: &str) -> io::Result<ReplayBus> {
+        let content = fs::read_to_string(trace_path)?;
+        ReplayBus::from_trace(dev_path, &content)
+    }
+
+    /// Replays the records of one bus from the json lines of a trace
+    pub fn from_trace(dev_path: &str, content: &str) -> io::Result<ReplayBus> {
+        let mut records = VecDeque::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let parsed = json::parse(line).map_err(io::Error::other)?;
+            let record = TraceRecord::from_json(&parsed)
+                .ok_or_else(|| io::Error::other(format!("Malformed trace record: {}", line)))?;
+            if record.bus == dev_path {
+                records.push_back(record);
+            }
+        }
+        println!("Loaded {} I2C trace records for {}", records.len(), dev_path);
+        Ok(ReplayBus {
+            dev_path: dev_path.to_string(),
+            records,
+            address: 0,
+        })
+    }
+
+    fn pop(&mut self, op: &str, register: Option<u8>) -> io::Result<TraceRecord> {
+        let record = self.records.pop_front().ok_or_else(|| {
+            io::Error::other(format!("I2C trace for {} exhausted at {}", self.dev_path, op))
+        })?;
+        if record.op != op || record.address != self.address || record.register != register {
+            return Err(io::Error::other(format!(
+                "I2C trace mismatch on {}: expected {} at {:x} register {:?}, got {} at {:x} register {:?}",
+                self.dev_path, record.op, record.address, record.register, op, self.address, register
+            )));
+        }
+        Ok(record)
+    }
+
+    fn next(&mut self, op: &str, register: Option<u8>) -> io::Result<Vec<u8>> {
+        let record = self.pop(op, register)?;
+        match record.error {
+            Some(error) => Err(io::Error::other(error)),
+            None => Ok(record.bytes),
+        }
+    }
+
+    fn next_byte(&mut self, op: &str, register: Option<u8>) -> io::Result<u8> {
+        let bytes = self.next(op, register)?;
+        bytes
+            .first()
+            .copied()
+            .ok_or_else(|| io::Error::other(format!("I2C trace record for {} has no data", op)))
+    }
+
+    /// Like next, but the written bytes must also be the recorded ones
+    fn next_write(&mut self, op: &str, register: Option<u8>, written: &[u8]) -> io::Result<()> {
+        let record = self.pop(op, register)?;
+        if record.bytes != written {
+            return Err(io::Error::other(format!(
+                "I2C trace mismatch on {}: {} at {:x} register {:?} expected bytes {:?}, got {:?}",
+                self.dev_path, op, self.address, register, record.bytes, written
+            )));
+        }
+        match record.error {
+            Some(error) => Err(io::Error::other(error)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Bus for ReplayBus {
+    fn reopen(&mut self) -> io::Result<()> {
+        self.next("reopen", None).map(|_| ())
+    }
+
+    fn i2c_functionality(&self) -> io::Result<String> {
+        Ok(format!("replay of {}", self.dev_path))
+    }
+
+    fn smbus_set_slave_address(&mut self, address: u16, tenbit: bool) -> io::Result<()> {
+        self.address = address;
+        self.next_write("set_slave_address", None, &[tenbit as u8])
+    }
+
+    fn smbus_set_pec(&mut self, pec: bool) -> io::Result<()> {
+        self.next_write("set_pec", None, &[pec as u8])
+    }
+
+    fn smbus_write_quick(&mut self) -> io::Result<()> {
+        self.next("write_quick", None).map(|_| ())
+    }
+
+    fn smbus_read_byte(&mut self) -> io::Result<u8> {
+        self.next_byte("read_byte", None)
+    }
+
+    fn smbus_write_byte(&mut self, value: u8) -> io::Result<()> {
+        self.next_write("write_byte", None, &[value])
+    }
+
+    fn smbus_read_byte_data(&mut self, register: u8) -> io::Result<u8> {
+        self.next_byte("read_byte_data", Some(register))
+    }
+
+    fn smbus_write_byte_data(&mut self, register: u8, value: u8) -> io::Result<()> {
+        self.next_write("write_byte_data", Some(register), &[value])
+    }
+
+    fn smbus_read_word_data(&mut self, register: u8) -> io::Result<u16> {
+        let bytes = self.next("read_word_data", Some(register))?;
+        match bytes[..] {
+            [low, high] => Ok(u16::from_le_bytes([low, high])),
+            _ => Err(io::Error::other("I2C trace record for read_word_data needs 2 bytes")),
+        }
+    }
+
+    fn smbus_write_word_data(&mut self, register: u8, value: u16) -> io::Result<()> {
+        self.next_write("write_word_data", Some(register), &value.to_le_bytes())
+    }
+
+    fn i2c_read_block_data(&mut self, register: u8, value: &mut [u8]) -> io::Result<usize> {
+        let bytes = self.next("read_block_data", Some(register))?;
+        let size = bytes.len().min(value.len());
+        value[..size].copy_from_slice(&bytes[..size]);
+        Ok(size)
+    }
+
+    fn i2c_write_block_data(&mut self, register: u8, value: &[u8]) -> io::Result<()> {
+        self.next_write("write_block_data", Some(register), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+    use crate::app_context::RecoveryConfig;
+    use crate::hw::i2c_mgmt::{I2cDevice, I2cTarget};
+    use crate::hw::thermometer::{Lps331ap, Thermometer};
+
+    const LPS331AP_ADDRESS: u16 = 0x5D;
+
+    // recorded from an LPS331AP: init, one temperature and one pressure read
+    const LPS331AP_TRACE: &str = r#"
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_block_data","address":93,"register":15,"bytes":[187,0,0,0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"write_block_data","address":93,"register":32,"bytes":[224],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_block_data","address":93,"register":43,"bytes":[224,0,0,0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_block_data","address":93,"register":44,"bytes":[1,0,0,0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_block_data","address":93,"register":40,"bytes":[0,0,0,0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_block_data","address":93,"register":41,"bytes":[84,0,0,0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":93,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_block_data","address":93,"register":42,"bytes":[63,0,0,0],"error":null}
+"#;
+
+    fn replay_device() -> I2cDevice {
+        let recovery = RecoveryConfig { retries: 0, backoff_ms: 0, stuck_threshold: 3 };
+        I2cDevice::replay(LPS331AP_TRACE, Vec::new(), recovery)
+    }
+
+    fn lps331ap(ctrl_reg1: u8) -> Lps331ap {
+        let init_config = HashMap::from([(String::from("CtrlReg1"), vec![ctrl_reg1])]);
+        Lps331ap::new(I2cTarget::new(LPS331AP_ADDRESS, false), init_config)
+    }
+
+    #[test]
+    fn replays_lps331ap_init_and_read() {
+        let mut i2c = replay_device();
+        let thermometer = lps331ap(0xE0);
+        thermometer.initialize(&mut i2c).unwrap();
+        assert_eq!(thermometer.read_temperature(&mut i2c).unwrap(), 44);
+        assert_eq!(thermometer.read_pressure(&mut i2c).unwrap(), 1013);
+    }
+
+    #[test]
+    fn replay_rejects_different_written_bytes() {
+        let mut i2c = replay_device();
+        let error = lps331ap(0x90).initialize(&mut i2c).unwrap_err();
+        assert!(error.contains("CTRL_REG1"), "{}", error);
+    }
+
+    #[test]
+    fn replay_rejects_different_pec_setting() {
+        let mut bus = ReplayBus::from_trace(I2cDevice::REPLAY_BUS, LPS331AP_TRACE).unwrap();
+        bus.smbus_set_slave_address(LPS331AP_ADDRESS, false).unwrap();
+        assert!(bus.smbus_set_pec(true).is_err());
+    }
+}
@@ -1,18 +1,70 @@
-extern crate i2c_linux;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+use crate::app_context::{DeviceData, MuxRoute, RecoveryConfig};
+use crate::hw::i2c_bus::Bus;
 
-use i2c_linux::I2c;
-use std::fs::File;
+#[derive(Clone, Copy, Debug)]
+pub struct I2cTarget {
+    pub address: u16,
+    pub ten_bit: bool,
+    pub pec: bool,
+    pub mux: Option<MuxRoute>,
+}
+
+impl I2cTarget {
+    pub fn new(address: u16, ten_bit: bool) -> I2cTarget {
+        I2cTarget {
+            address,
+            ten_bit,
+            pec: false,
+            mux: None,
+        }
+    }
+}
+
+impl From<&DeviceData> for I2cTarget {
+    fn from(device_data: &DeviceData) -> Self {
+        I2cTarget {
+            address: device_data.address,
+            ten_bit: device_data.ten_bit_address,
+            pec: device_data.pec,
+            mux: device_data.mux,
+        }
+    }
+}
+
+/// Shared with metrics and status readers, so they don't wait for the bus lock
+#[derive(Debug, Default)]
+pub struct I2cCounters {
+    pub errors: AtomicU32,
+    pub retries: AtomicU32,
+    pub recoveries: AtomicU32,
+    pub recovered: AtomicBool, // set when the bus was reopened, until drivers on it are initialized again
+}
 
 pub struct I2cDevice {
     dev_path: String,
-    dev: I2c<File>,
+    dev: Box<dyn Bus>,
+    muxes: Vec<u16>, // addresses of the multiplexers on this bus
+    mux_channels: HashMap<u16, u8>, // last control byte written to each multiplexer
+    recovery: RecoveryConfig,
+    counters: Arc<I2cCounters>,
+    consecutive_failures: u32,
 }
 
 impl I2cDevice {
-    pub fn new(device_path: String) -> I2cDevice {
+    pub fn new(device_path: String, dev: Box<dyn Bus>, muxes: Vec<u16>, recovery: RecoveryConfig) -> I2cDevice {
         I2cDevice {
-            dev_path: device_path.clone(),
-            dev: I2c::from_path(device_path).expect("Failed to create i2c device"),
+            dev_path: device_path,
+            dev,
+            muxes,
+            mux_channels: HashMap::new(),
+            recovery,
+            counters: Arc::new(I2cCounters::default()),
+            consecutive_failures: 0,
         }
     }
 
@@ -20,30 +72,152 @@ impl I2cDevice {
         &self.dev_path
     }
 
+    pub fn counters(&self) -> Arc<I2cCounters> {
+        self.counters.clone()
+    }
+
+    /// Returns true once after the bus was reopened, so drivers can be initialized again
+    pub fn take_recovered(&self) -> bool {
+        self.counters.recovered.swap(false, Ordering::Relaxed)
+    }
+
+    fn reset(&mut self) {
+        println!("I2C bus {} looks stuck, reopening", self.dev_path);
+        match self.dev.reopen() {
+            Ok(_) => {
+                self.mux_channels.clear();
+                self.counters.recoveries.fetch_add(1, Ordering::Relaxed);
+                self.counters.recovered.store(true, Ordering::Relaxed);
+            }
+            Err(e) => println!("Reopening I2C bus {} failed: {}", self.dev_path, e),
+        }
+        self.consecutive_failures = 0;
+    }
+
+    fn with_recovery<T>(&mut self, mut transaction: impl FnMut(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        const MAX_BACKOFF: Duration = Duration::from_secs(1);
+        let mut backoff = Duration::from_millis(self.recovery.backoff_ms).min(MAX_BACKOFF);
+        let mut result = transaction(self);
+        for _ in 0..self.recovery.retries {
+            if result.is_ok() {
+                break;
+            }
+            thread::sleep(backoff);
+            backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+            self.counters.retries.fetch_add(1, Ordering::Relaxed);
+            // mux state is unknown after a failed transaction
+            self.mux_channels.clear();
+            result = transaction(self);
+        }
+
+        match result {
+            Ok(_) => self.consecutive_failures = 0,
+            Err(_) => {
+                self.counters.errors.fetch_add(1, Ordering::Relaxed);
+                self.consecutive_failures += 1;
+                if self.consecutive_failures >= self.recovery.stuck_threshold {
+                    self.reset();
+                }
+            }
+        }
+        result
+    }
+
     pub fn functionality(&self) -> Result<String, String> {
         let ret = self.dev.i2c_functionality();
         println!("I2C functionality: {:?}", ret);
         match ret {
-            Ok(ret_func) => Ok(format!("{:?}", ret_func).to_string()),
+            Ok(ret_func) => Ok(ret_func),
             Err(error) => Err("I2C functionality error".to_string() + &error.to_string()),
         }
     }
 
+    fn write_mux_control(&mut self, mux_address: u16, control: u8) -> Result<(), String> {
+        if self.mux_channels.get(&mux_address) == Some(&control) {
+            return Ok(());
+        }
+        if let Err(e) = self.dev.smbus_set_slave_address(mux_address, false) {
+            println!("Setting multiplexer address {:x} failed: {}", mux_address, e);
+            return Err(format!("Setting multiplexer address {:x} failed", mux_address));
+        }
+        match self.dev.smbus_write_byte(control) {
+            Ok(_) => {
+                self.mux_channels.insert(mux_address, control);
+                Ok(())
+            }
+            Err(e) => {
+                println!("Writing control byte {:#x} to multiplexer {:x} failed: {}", control, mux_address, e);
+                self.mux_channels.remove(&mux_address);
+                Err(format!("Writing control byte {:#x} to multiplexer {:x} failed", control, mux_address))
+            }
+        }
+    }
+
+    /// Closes all channels of every multiplexer on the bus except `keep`, devices sharing an
+    /// address behind different multiplexers would otherwise answer together
+    fn deselect_muxes(&mut self, keep: Option<u16>) -> Result<(), String> {
+        for index in 0..self.muxes.len() {
+            let mux_address = self.muxes[index];
+            if Some(mux_address) != keep {
+                self.write_mux_control(mux_address, 0x00)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn select_mux_channel(&mut self, route: &MuxRoute) -> Result<(), String> {
+        let control = 1u8 << route.channel;
+        if self.mux_channels.get(&route.mux_address) != Some(&control) {
+            println!("Selecting channel {} on multiplexer {:x}", route.channel, route.mux_address);
+        }
+        self.write_mux_control(route.mux_address, control)
+    }
+
+    fn select_slave(&mut self, target: &I2cTarget) -> Result<(), String> {
+        self.deselect_muxes(target.mux.map(|route| route.mux_address))?;
+        if let Some(route) = &target.mux {
+            self.select_mux_channel(route)?;
+        }
+        if let Err(e) = self.dev.smbus_set_slave_address(target.address, target.ten_bit) {
+            println!("Setting slave address {:x} failed: {}", target.address, e);
+            return Err(format!("Setting slave address {:x} failed", target.address));
+        }
+        if let Err(e) = self.dev.smbus_set_pec(target.pec) {
+            println!("Setting PEC for slave address {:x} failed: {}", target.address, e);
+            return Err(format!("Setting PEC for slave address {:x} failed", target.address));
+        }
+        Ok(())
+    }
+
     pub fn write_register(
         &mut self,
-        slave_address: u16,
+        target: &I2cTarget,
         register: u8,
         value: &Vec<u8>,
     ) -> Result<(), String> {
-        self.dev
-            .smbus_set_slave_address(slave_address, false)
-            .expect("Error during setting slave address");
+        self.with_recovery(|i2c| i2c.try_write_register(target, register, value))
+    }
+
+    fn try_write_register(
+        &mut self,
+        target: &I2cTarget,
+        register: u8,
+        value: &Vec<u8>,
+    ) -> Result<(), String> {
+        self.select_slave(target)?;
 
         println!(
             "Writing value {:?} to register {} at slave address {:x}",
-            value, register, slave_address
+            value, register, target.address
         );
-        match self.dev.i2c_write_block_data(register, value.as_slice()) {
+        // the kernel only adds PEC to SMBus transfers, plain I2C block writes would go out unprotected
+        let result = match (target.pec, value.as_slice()) {
+            (false, _) => self.dev.i2c_write_block_data(register, value.as_slice()),
+            (true, [byte]) => self.dev.smbus_write_byte_data(register, *byte),
+            (true, [first, second]) => self.dev.smbus_write_word_data(register, u16::from_le_bytes([*first, *second])),
+            (true, _) => return Err(format!("PEC writes are limited to 2 bytes, {} requested", value.len())),
+        };
+        match result {
             Ok(_) => Ok(()),
             Err(_) => {
                 println!("Write failed for register {}", register);
@@ -54,20 +228,39 @@ impl I2cDevice {
 
     pub fn get_register(
         &mut self,
-        slave_address: u16,
+        target: &I2cTarget,
+        register: u8,
+        num_of_bytes: usize,
+    ) -> Result<Vec<u8>, String> {
+        self.with_recovery(|i2c| i2c.try_get_register(target, register, num_of_bytes))
+    }
+
+    fn try_get_register(
+        &mut self,
+        target: &I2cTarget,
         register: u8,
         num_of_bytes: usize,
     ) -> Result<Vec<u8>, String> {
-        self.dev
-            .smbus_set_slave_address(slave_address, false)
-            .expect("Error during setting slave address");
+        self.select_slave(target)?;
         let mut buffer: [u8; 4] = [0; 4];
 
         println!(
             "Reading {} bytes from register {} at slave address {:x}",
-            num_of_bytes, register, slave_address
+            num_of_bytes, register, target.address
         );
-        match self.dev.i2c_read_block_data(register, &mut buffer) {
+        let result = match (target.pec, num_of_bytes) {
+            (false, _) => self.dev.i2c_read_block_data(register, &mut buffer),
+            (true, 1) => self.dev.smbus_read_byte_data(register).map(|byte| {
+                buffer[0] = byte;
+                1
+            }),
+            (true, 2) => self.dev.smbus_read_word_data(register).map(|word| {
+                buffer[..2].copy_from_slice(&word.to_le_bytes());
+                2
+            }),
+            (true, _) => return Err(format!("PEC reads are limited to 2 bytes, {} requested", num_of_bytes)),
+        };
+        match result {
             Ok(size) => {
                 if size < num_of_bytes {
                     println!("Read {} bytes, expected {}", size, num_of_bytes);
@@ -84,16 +277,38 @@ impl I2cDevice {
         }
     }
 
-    pub fn get_byte_from_register(
+    /// Register read for debugging requests: one attempt, not counted as a bus error, so probing
+    /// an absent or misbehaving device can't make the bus look stuck and get it reopened
+    pub fn debug_read_register(
         &mut self,
-        slave_address: u16,
+        target: &I2cTarget,
+        register: u8,
+        num_of_bytes: usize,
+    ) -> Result<Vec<u8>, String> {
+        match num_of_bytes {
+            1 => self.try_get_byte_from_register(target, register).map(|byte| vec![byte]),
+            _ => self.try_get_register(target, register, num_of_bytes),
+        }
+    }
+
+    /// Register write for debugging requests, one attempt outside of stuck detection like debug_read_register
+    pub fn debug_write_register(
+        &mut self,
+        target: &I2cTarget,
+        register: u8,
+        value: &Vec<u8>,
+    ) -> Result<(), String> {
+        self.try_write_register(target, register, value)
+    }
+
+    fn try_get_byte_from_register(
+        &mut self,
+        target: &I2cTarget,
         register: u8,
     ) -> Result<u8, String> {
-        self.dev
-            .smbus_set_slave_address(slave_address, false)
-            .expect("Error during setting slave address");
+        self.select_slave(target)?;
 
-        println!("Reading 1 byte from register {} at slave address {:x}", register, slave_address);
+        println!("Reading 1 byte from register {} at slave address {:x}", register, target.address);
         match self.dev.smbus_read_byte_data(register) {
             Ok(byte) => Ok(byte),
             Err(e) => {
@@ -102,4 +317,113 @@ impl I2cDevice {
             }
         }
     }
+
+    /// Puts the bus in a known state before probing: PEC left on by the last transaction
+    /// would corrupt the probes and only the channel of `mux`, if any, adds its devices
+    pub fn prepare_scan(&mut self, mux: Option<&MuxRoute>) -> Result<(), String> {
+        if let Err(e) = self.dev.smbus_set_pec(false) {
+            println!("Disabling PEC on {} failed: {}", self.dev_path, e);
+            return Err(format!("Disabling PEC on {} failed", self.dev_path));
+        }
+        self.deselect_muxes(mux.map(|route| route.mux_address))?;
+        match mux {
+            Some(route) => self.select_mux_channel(route),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks if any device answers at the address, same way as i2cdetect does: quick write
+    /// for most addresses, read byte for ranges where quick write could corrupt EEPROMs.
+    pub fn probe(&mut self, address: u16) -> bool {
+        if self.dev.smbus_set_slave_address(address, false).is_err() {
+            return false;
+        }
+        let result = match address {
+            0x30..=0x37 | 0x50..=0x5F => self.dev.smbus_read_byte().map(|_| ()),
+            _ => self.dev.smbus_write_quick(),
+        };
+        result.is_ok()
+    }
+}
+
+#[cfg(test)]
+impl I2cDevice {
+    /// Bus the test traces are recorded on
+    pub const REPLAY_BUS: &str = "/dev/i2c-1";
+
+    /// Device replaying `trace` instead of talking to hardware, for driver and bus tests
+    pub fn replay(trace: &str, muxes: Vec<u16>, recovery: RecoveryConfig) -> I2cDevice {
+        let bus = crate::hw::i2c_bus::ReplayBus::from_trace(Self::REPLAY_BUS, trace).unwrap();
+        I2cDevice::new(Self::REPLAY_BUS.to_string(), Box::new(bus), muxes, recovery)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use super::*;
+
+    // two reads from an address nobody answers on
+    const ABSENT_DEVICE_TRACE: &str = r#"
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_byte_data","address":80,"register":0,"bytes":[],"error":"No such device or address"}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_byte_data","address":80,"register":0,"bytes":[],"error":"No such device or address"}
+"#;
+
+    const RECOVERY: RecoveryConfig = RecoveryConfig { retries: 0, backoff_ms: 0, stuck_threshold: 1 };
+
+    #[test]
+    fn debug_reads_do_not_count_towards_stuck_bus() {
+        let mut i2c = I2cDevice::replay(ABSENT_DEVICE_TRACE, Vec::new(), RECOVERY);
+        let target = I2cTarget::new(0x50, false);
+        assert!(i2c.debug_read_register(&target, 0, 1).is_err());
+        assert!(i2c.debug_read_register(&target, 0, 1).is_err());
+        // a reset would have needed a reopen record in the trace
+        assert!(!i2c.take_recovered());
+        let counters = i2c.counters();
+        assert_eq!(counters.errors.load(Ordering::Relaxed), 0);
+        assert_eq!(counters.recoveries.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn failed_transactions_reset_stuck_bus() {
+        let trace = r#"
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"read_block_data","address":80,"register":0,"bytes":[],"error":"Remote I/O error"}
+{"bus":"/dev/i2c-1","op":"reopen","address":80,"register":null,"bytes":[],"error":null}
+"#;
+        let mut i2c = I2cDevice::replay(trace, Vec::new(), RECOVERY);
+        assert!(i2c.get_register(&I2cTarget::new(0x50, false), 0, 1).is_err());
+        assert!(i2c.take_recovered());
+        let counters = i2c.counters();
+        assert_eq!(counters.errors.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.recoveries.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn scan_starts_without_pec_and_with_muxes_closed() {
+        let trace = r#"
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":112,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"write_byte","address":112,"register":null,"bytes":[4],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":80,"register":null,"bytes":[1],"error":null}
+{"bus":"/dev/i2c-1","op":"read_byte_data","address":80,"register":0,"bytes":[7],"error":null}
+{"bus":"/dev/i2c-1","op":"set_pec","address":80,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":112,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"write_byte","address":112,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"set_slave_address","address":72,"register":null,"bytes":[0],"error":null}
+{"bus":"/dev/i2c-1","op":"write_quick","address":72,"register":null,"bytes":[],"error":null}
+"#;
+        let mut i2c = I2cDevice::replay(trace, vec![0x70], RECOVERY);
+        let mut target = I2cTarget::new(0x50, false);
+        target.pec = true;
+        target.mux = Some(MuxRoute { mux_address: 0x70, channel: 2 });
+        assert_eq!(i2c.debug_read_register(&target, 0, 1), Ok(vec![7]));
+        assert_eq!(i2c.prepare_scan(None), Ok(()));
+        assert!(i2c.probe(0x48));
+    }
 }
@@ -1,13 +1,17 @@
 use std::collections::HashMap;
-use crate::hw::i2c_mgmt::I2cDevice;
+use crate::hw::i2c_mgmt::{I2cDevice, I2cTarget};
 
-pub trait Thermometer {
+pub trait Thermometer: Send + Sync {
     fn initialize(&self, i2c: &mut I2cDevice) -> Result<(), String>;
     fn read_temperature(&self, i2c: &mut I2cDevice) -> Result<i16, String>;
+    /// Pressure in hPa, for chips that measure it next to the temperature
+    fn read_pressure(&self, _i2c: &mut I2cDevice) -> Result<u16, String> {
+        Err(String::from("Thermometer has no pressure sensor"))
+    }
 }
 
 pub struct Lps331ap {
-    address: u16,
+    target: I2cTarget,
     ctrl_reg1_value: u8,
 }
 
@@ -17,13 +21,15 @@ impl Lps331ap {
     const CTRL_REG1: u8 = 0x20;
     const TEMP_OUT_L: u8 = 0x2B;
     const TEMP_OUT_H: u8 = 0x2C;
-    pub fn new(addr: u16, init_config: HashMap<String, Vec<u8>>) -> Self {
+    const PRESS_OUT_XL: u8 = 0x28; // followed by PRESS_OUT_L and PRESS_OUT_H
+    const PRESS_COUNTS_PER_HPA: f32 = 4096.0;
+    pub fn new(target: I2cTarget, init_config: HashMap<String, Vec<u8>>) -> Self {
         let ctrl_reg1 = init_config.get("CtrlReg1").expect("CTRL_REG1 not found in init_config");
         if ctrl_reg1.len() != 1 {
             panic!("CTRL_REG1 value must be 1 byte for LPS331AP");
         }
         Lps331ap {
-            address: addr,
+            target,
             ctrl_reg1_value: *ctrl_reg1.first().unwrap(),
         }
     }
@@ -33,7 +39,7 @@ impl Thermometer for Lps331ap {
     fn initialize(&self, i2c: &mut I2cDevice) -> Result<(), String> {
         println!("Initializing LPS331AP thermometer");
 
-        let who_am_i = match i2c.get_register(self.address, Self::WHO_AM_I, 1) {
+        let who_am_i = match i2c.get_register(&self.target, Self::WHO_AM_I, 1) {
             Ok(val) => val[0],
             Err(e) => return Err(format!("Failed to read WHO_AM_I from LPS331AP: {}", e)),
         };
@@ -46,7 +52,7 @@ impl Thermometer for Lps331ap {
         }
 
         match i2c.write_register(
-            self.address,
+            &self.target,
             Self::CTRL_REG1,
             &Vec::from([self.ctrl_reg1_value])
         ) {
@@ -58,11 +64,11 @@ impl Thermometer for Lps331ap {
     fn read_temperature(&self, i2c: &mut I2cDevice) -> Result<i16, String> {
         println!("Reading temperature from LPS331AP");
 
-        let temp_out_l = match i2c.get_register(self.address, Self::TEMP_OUT_L, 1) {
+        let temp_out_l = match i2c.get_register(&self.target, Self::TEMP_OUT_L, 1) {
             Ok(val) => val[0],
             Err(e) => return Err(format!("Failed to read TEMP_OUT_L from LPS331AP: {}", e)),
         };
-        let temp_out_h = match i2c.get_register(self.address, Self::TEMP_OUT_H, 1) {
+        let temp_out_h = match i2c.get_register(&self.target, Self::TEMP_OUT_H, 1) {
             Ok(val) => val[0],
             Err(e) => return Err(format!("Failed to read TEMP_OUT_H from LPS331AP: {}", e)),
         };
@@ -71,4 +77,22 @@ impl Thermometer for Lps331ap {
         let temperature_c = (raw_temp as f32 / 480.0) + 42.5;
         Ok(temperature_c.round() as i16)
     }
-}
\ No newline at end of file
+
+    fn read_pressure(&self, i2c: &mut I2cDevice) -> Result<u16, String> {
+        println!("Reading pressure from LPS331AP");
+
+        let mut press_out = [0u8; 4];
+        for (offset, byte) in press_out.iter_mut().take(3).enumerate() {
+            let register = Self::PRESS_OUT_XL + offset as u8;
+            *byte = match i2c.get_register(&self.target, register, 1) {
+                Ok(val) => val[0],
+                Err(e) => return Err(format!("Failed to read PRESS_OUT register {:#x} from LPS331AP: {}", register, e)),
+            };
+        }
+
+        // 24 bit two's complement, shifted up to sign extend
+        let raw_pressure = i32::from_le_bytes(press_out) << 8 >> 8;
+        let pressure_hpa = raw_pressure as f32 / Self::PRESS_COUNTS_PER_HPA;
+        Ok(pressure_hpa.round().max(0.0) as u16)
+    }
+}
@@ -1,14 +1,30 @@
+mod access;
 mod app_context;
+mod discovery;
+mod http_api;
 mod hw;
-mod msg;
+mod metrics;
+mod mqtt;
+mod sampler;
+mod station;
+mod storage;
+mod subscriptions;
+mod transport;
+mod worker_pool;
 
 use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
 use log::{error, info};
-use crate::msg::MessageId;
+use plantstation_protocol::msg::{self, MessageId};
+use plantstation_protocol::msg::codec::{self, Message};
 use String;
 use clap::{Parser, arg};
 use msg::ps::StatusType;
-use crate::app_context::AppContext;
+use crate::app_context::{AppContext, I2cTraceMode, MuxRoute, Sensor};
+use crate::hw::I2cTarget;
+use crate::station::Station;
+use crate::transport::Peer;
+use crate::worker_pool::WorkerPool;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -22,52 +38,119 @@ struct Args {
 
     #[arg(long)]
     hw_config: String,
+
+    /// Also accept length-prefixed requests over TCP on the same address
+    #[arg(long, default_value_t = false)]
+    tcp: bool,
+
+    /// Also accept length-prefixed requests on this unix socket
+    #[arg(long)]
+    unix_socket: Option<String>,
+
+    /// Permissions of the unix socket file, in octal
+    #[arg(long, default_value = "660", value_parser = parse_mode)]
+    unix_socket_mode: u32,
+
+    /// Serve the JSON HTTP API on this port, it has no authentication and refuses to start when the config requires it
+    #[arg(long)]
+    http_port: Option<u16>,
+
+    /// Number of threads handling requests concurrently
+    #[arg(long, default_value = "4")]
+    workers: usize,
+
+    /// Requests waiting for a worker, further requests are dropped until the workers catch up
+    #[arg(long, default_value = "64")]
+    worker_queue: usize,
+
+    /// Record every I2C transaction to this file
+    #[arg(long, conflicts_with = "i2c_replay")]
+    i2c_trace: Option<String>,
+
+    /// Replay I2C transactions from a recorded trace instead of using real buses
+    #[arg(long)]
+    i2c_replay: Option<String>,
+
+    /// Port answering broadcast and multicast discovery requests
+    #[arg(long, default_value_t = msg::DISCOVERY_PORT)]
+    discovery_port: u16,
+
+    /// Do not answer discovery requests, the default when --ip is a loopback address
+    #[arg(long, default_value_t = false, conflicts_with = "discovery")]
+    no_discovery: bool,
+
+    /// Answer discovery requests even though --ip is a loopback address
+    #[arg(long, default_value_t = false)]
+    discovery: bool,
+}
+
+fn parse_mode(arg: &str) -> Result<u32, String> {
+    u32::from_str_radix(arg, 8).map_err(|e| e.to_string())
+}
+
+fn route(msg_id: u8, buffer: &[u8], station: &Station, peer: &Peer) -> Vec<u8> {
+    const AUTHENTICATED_MSG_ID: u8 = MessageId::Authenticated as u8;
+    if msg_id == AUTHENTICATED_MSG_ID {
+        return route_authenticated(buffer, station, peer);
+    }
+    if station.auth_required() {
+        error!("Rejecting unauthenticated {} from {}", MessageId::name(msg_id), peer);
+        return Vec::new();
+    }
+    dispatch(msg_id, buffer, station, peer)
+}
+
+/// Verifies the envelope, handles the message inside and signs the response with the same key
+fn route_authenticated(buffer: &[u8], station: &Station, peer: &Peer) -> Vec<u8> {
+    let Some(auth) = station.authenticator() else {
+        error!("Authenticated message from {}, but no key configured", peer);
+        return Vec::new();
+    };
+    let frame = match auth.open(buffer) {
+        Ok(frame) => frame,
+        Err(e) => {
+            error!("Rejecting message from {}: {}", peer, e);
+            return Vec::new();
+        }
+    };
+    if frame[0] == MessageId::Authenticated as u8 {
+        error!("Rejecting nested authenticated message from {}", peer);
+        return Vec::new();
+    }
+    let resp = dispatch(frame[0], &frame[1..], station, peer);
+    if resp.is_empty() {
+        return resp;
+    }
+    auth.seal(&resp)
 }
 
-fn route(msg_id: u8, buffer: &[u8], ps_hw: &mut hw::Hw) -> Vec<u8> {
+fn dispatch(msg_id: u8, buffer: &[u8], station: &Station, peer: &Peer) -> Vec<u8> {
     info!("Routing message id {}", msg_id);
+    station.requests().increment(msg_id);
     const GET_STATUS_MSG_ID: u8 = MessageId::GetStatusReq as u8;
     const GET_ADC_VALUE_MSG_ID: u8 = MessageId::GetAdcValueReq as u8;
     const GET_HYGROMETER_STATUS_MSG_ID: u8 = MessageId::GetHygrometerStatusReq as u8;
     const GET_TEMPERATURE_MSG_ID: u8 = MessageId::GetTemperatureReq as u8;
+    const SCAN_BUS_MSG_ID: u8 = MessageId::ScanBusReq as u8;
+    const READ_REGISTER_MSG_ID: u8 = MessageId::ReadRegisterReq as u8;
+    const WRITE_REGISTER_MSG_ID: u8 = MessageId::WriteRegisterReq as u8;
+    const GET_HISTORY_MSG_ID: u8 = MessageId::GetHistoryReq as u8;
+    const SUBSCRIBE_MSG_ID: u8 = MessageId::SubscribeReq as u8;
+    const UNSUBSCRIBE_MSG_ID: u8 = MessageId::UnsubscribeReq as u8;
+    const READ_SENSORS_MSG_ID: u8 = MessageId::ReadSensorsReq as u8;
 
     match msg_id {
-        GET_STATUS_MSG_ID => {
-            match bincode::deserialize::<msg::ps::GetStatusReq>(buffer) {
-                Ok(msg) => handle_get_status_req(&msg, ps_hw),
-                Err(e) => {
-                    error!("{}", e);
-                    Vec::new()
-                }
-            }
-        },
-        GET_ADC_VALUE_MSG_ID => {
-            match bincode::deserialize::<msg::ps::GetAdcValueReq>(buffer) {
-                Ok(msg) => handle_get_adc_value_req(&msg, ps_hw),
-                Err(e) => {
-                    error!("{}", e);
-                    Vec::new()
-                }
-            }
-        },
-        GET_HYGROMETER_STATUS_MSG_ID => {
-            match bincode::deserialize::<msg::ps::GetHygrometerStatusReq>(buffer) {
-                Ok(msg) => handle_get_higrometer_status_req(&msg, ps_hw),
-                Err(e) => {
-                    error!("GetHygrometerStatusReq error: {}", e);
-                    Vec::new()
-                }
-            }
-        },
-        GET_TEMPERATURE_MSG_ID => {
-            match bincode::deserialize::<msg::ps::GetTemperatureReq>(buffer) {
-                Ok(_) => handle_get_temperature_req(ps_hw),
-                Err(e) => {
-                    error!("GetTemperatureReq error: {}", e);
-                    Vec::new()
-                }
-            }
-        },
+        GET_STATUS_MSG_ID => decode_and_handle(buffer, |msg| handle_get_status_req(msg, station)),
+        GET_ADC_VALUE_MSG_ID => decode_and_handle(buffer, |msg| handle_get_adc_value_req(msg, station)),
+        GET_HYGROMETER_STATUS_MSG_ID => decode_and_handle(buffer, |msg| handle_get_higrometer_status_req(msg, station)),
+        GET_TEMPERATURE_MSG_ID => decode_and_handle(buffer, |msg| handle_get_temperature_req(msg, station)),
+        SCAN_BUS_MSG_ID => decode_and_handle(buffer, |msg| handle_scan_bus_req(msg, station.hw())),
+        READ_REGISTER_MSG_ID => decode_and_handle(buffer, |msg| handle_read_register_req(msg, station.hw())),
+        WRITE_REGISTER_MSG_ID => decode_and_handle(buffer, |msg| handle_write_register_req(msg, station.hw())),
+        GET_HISTORY_MSG_ID => decode_and_handle(buffer, |msg| handle_get_history_req(msg, station)),
+        SUBSCRIBE_MSG_ID => decode_and_handle(buffer, |msg| handle_subscribe_req(msg, station, peer)),
+        UNSUBSCRIBE_MSG_ID => decode_and_handle(buffer, |msg| handle_unsubscribe_req(msg, station, peer)),
+        READ_SENSORS_MSG_ID => decode_and_handle(buffer, |msg| handle_read_sensors_req(msg, station)),
         _ => {
             info!("Received unknown opcode {}", msg_id);
             Vec::new()
@@ -75,71 +158,293 @@ fn route(msg_id: u8, buffer: &[u8], ps_hw: &mut hw::Hw) -> Vec<u8> {
     }
 }
 
-fn handle_get_status_req(req: &msg::ps::GetStatusReq, plantstation_hw: &mut hw::Hw) -> Vec<u8> {
+fn decode_and_handle<M: Message>(buffer: &[u8], handler: impl FnOnce(&M) -> Vec<u8>) -> Vec<u8> {
+    match codec::decode_payload::<M>(buffer) {
+        Ok(msg) => handler(&msg),
+        Err(e) => {
+            error!("{}", e);
+            Vec::new()
+        }
+    }
+}
+
+fn handle_get_status_req(req: &msg::ps::GetStatusReq, station: &Station) -> Vec<u8> {
+    let plantstation_hw = station.hw();
     info!("Handling GetStatusReq: {:?}", req);
     let mut resp = msg::ps::GetStatusResp::new(String::new());
     match req.get_status() {
         StatusType::I2C => resp.status = plantstation_hw.i2c_status(),
         StatusType::ADC => resp.status = plantstation_hw.adc_status(),
+        StatusType::Access => resp.status = station.access().status(),
         StatusType::Unknown => resp.status = String::from("Unknown"),
     }
 
-    let mut out = bincode::serialize(&resp).unwrap();
-    out.insert(0, MessageId::GetStatusResp as u8);
-    out
+    codec::encode(&resp)
 }
 
-fn handle_get_adc_value_req(req: &msg::ps::GetAdcValueReq, plantstation_hw: &mut hw::Hw) -> Vec<u8> {
+fn handle_get_adc_value_req(req: &msg::ps::GetAdcValueReq, station: &Station) -> Vec<u8> {
     info!("Handling GetAdcValueReq: {:?}", req);
-    let mut resp = msg::ps::GetAdcValueResp::new(0);
-    match plantstation_hw.read_adc_value(req.is_converted(), req.channel) {
+    let sensor = match req.is_converted() {
+        true => Sensor::AdcMillivolts(req.channel),
+        false => Sensor::AdcRaw(req.channel),
+    };
+    let reading = station.read(sensor, req.fresh);
+    let mut resp = msg::ps::GetAdcValueResp::new(0, reading.age_ms());
+    match reading.value {
         Ok(val) => {
-            resp.value = val;
+            resp.value = val as u16;
         }
         Err(e) => {
             error!("Error reading ADC value: {}", e);
         }
     }
 
-    let mut out = bincode::serialize(&resp).unwrap();
-    out.insert(0, MessageId::GetAdcValueResp as u8);
-    out
+    codec::encode(&resp)
 }
 
-fn handle_get_higrometer_status_req(req: &msg::ps::GetHygrometerStatusReq, plantstation_hw: &mut hw::Hw) -> Vec<u8> {
+fn handle_get_higrometer_status_req(req: &msg::ps::GetHygrometerStatusReq, station: &Station) -> Vec<u8> {
     info!("Handling GetHygrometerStatusReq: {:?}", req);
-    let mut resp = msg::ps::GetHygrometerStatusResp::new(0);
+    let reading = station.read(Sensor::Humidity(req.channel), req.fresh);
+    let mut resp = msg::ps::GetHygrometerStatusResp::new(0, reading.age_ms());
 
-    resp.humidity = plantstation_hw.read_humidity(req.channel).unwrap_or_else(|_| 0);
+    resp.humidity = reading.value.map(|humidity| humidity as u8).unwrap_or_else(|_| 0);
 
-    let mut out = bincode::serialize(&resp).unwrap_or_else(|_| {
-        error!("Error serializing GetHygrometerStatusResp");
-        Vec::from([0])
-    });
-    out.insert(0, MessageId::GetHygrometerStatusResp as u8);
-    out
+    codec::encode(&resp)
 }
 
-fn handle_get_temperature_req(plantstation_hw: &mut hw::Hw) -> Vec<u8> {
-    info!("Handling GetTemperatureReq");
-    let mut resp = msg::ps::GetTemperatureResp::new(-273);
-    resp.temperature = plantstation_hw.read_temperature().unwrap_or(-273);
+fn handle_get_temperature_req(req: &msg::ps::GetTemperatureReq, station: &Station) -> Vec<u8> {
+    info!("Handling GetTemperatureReq: {:?}", req);
+    let reading = station.read(Sensor::Temperature, req.fresh);
+    let mut resp = msg::ps::GetTemperatureResp::new(-273, reading.age_ms());
+    resp.temperature = reading.value.map(|temperature| temperature as i16).unwrap_or(-273);
+
+    codec::encode(&resp)
+}
 
-    let mut out = bincode::serialize(&resp).unwrap_or_else(|_| {
-        info!("Error serializing GetTemperatureResp");
-        Vec::from([0])
+fn mux_route(mux: Option<msg::ps::MuxChannel>) -> Option<MuxRoute> {
+    mux.map(|mux| MuxRoute { mux_address: mux.mux_address, channel: mux.channel })
+}
+
+fn handle_scan_bus_req(req: &msg::ps::ScanBusReq, plantstation_hw: &hw::Hw) -> Vec<u8> {
+    info!("Handling ScanBusReq: {:?}", req);
+    let resp = match plantstation_hw.scan_bus(&req.bus, mux_route(req.mux)) {
+        Ok(found) => {
+            let devices = found
+                .into_iter()
+                .map(|(address, device)| msg::ps::ScannedDevice::new(address, device))
+                .collect();
+            msg::ps::ScanBusResp::new(devices, String::new())
+        }
+        Err(e) => {
+            error!("Error scanning bus: {}", e);
+            msg::ps::ScanBusResp::new(Vec::new(), e)
+        }
+    };
+
+    codec::encode(&resp)
+}
+
+fn handle_read_register_req(req: &msg::ps::ReadRegisterReq, plantstation_hw: &hw::Hw) -> Vec<u8> {
+    info!("Handling ReadRegisterReq: {:?}", req);
+    let mut target = I2cTarget::new(req.address, req.ten_bit);
+    target.mux = mux_route(req.mux);
+    let resp = match plantstation_hw.read_register(&req.bus, target, req.register, req.length) {
+        Ok(data) => msg::ps::ReadRegisterResp::new(data, String::new()),
+        Err(e) => {
+            error!("Error reading register: {}", e);
+            msg::ps::ReadRegisterResp::new(Vec::new(), e)
+        }
+    };
+
+    codec::encode(&resp)
+}
+
+fn handle_write_register_req(req: &msg::ps::WriteRegisterReq, plantstation_hw: &hw::Hw) -> Vec<u8> {
+    info!("Handling WriteRegisterReq: {:?}", req);
+    let mut target = I2cTarget::new(req.address, req.ten_bit);
+    target.mux = mux_route(req.mux);
+    let resp = match plantstation_hw.write_register(&req.bus, target, req.register, &req.data) {
+        Ok(_) => msg::ps::WriteRegisterResp::new(String::new()),
+        Err(e) => {
+            error!("Error writing register: {}", e);
+            msg::ps::WriteRegisterResp::new(e)
+        }
+    };
+
+    codec::encode(&resp)
+}
+
+fn get_history_chunk(req: &msg::ps::GetHistoryReq, station: &Station) -> Result<msg::ps::GetHistoryResp, String> {
+    const BUCKETS_PER_CHUNK: usize = 32; // keeps each datagram well below 1024 bytes
+    let history = station.history().ok_or("History storage disabled in configuration")?;
+    let sensor = Sensor::from_name(&req.sensor).ok_or(format!("Unknown sensor {}", req.sensor))?;
+    let buckets = history.aggregate(sensor, req.from_ms, req.to_ms, req.resolution_ms as i64)?;
+
+    let chunks = buckets.len().div_ceil(BUCKETS_PER_CHUNK).max(1);
+    let chunks = u16::try_from(chunks)
+        .map_err(|_| format!("Query needs {} chunks, more than {}, use a coarser resolution", chunks, u16::MAX))?;
+    if req.chunk >= chunks {
+        return Err(format!("Chunk {} out of range, {} available", req.chunk, chunks));
+    }
+    let chunk_buckets = buckets
+        .iter()
+        .skip(req.chunk as usize * BUCKETS_PER_CHUNK)
+        .take(BUCKETS_PER_CHUNK)
+        .map(|bucket| msg::ps::HistoryBucket::new(bucket.start_ms, bucket.min, bucket.max, bucket.avg, bucket.count))
+        .collect();
+    Ok(msg::ps::GetHistoryResp::new(req.chunk, chunks, chunk_buckets, String::new()))
+}
+
+fn handle_get_history_req(req: &msg::ps::GetHistoryReq, station: &Station) -> Vec<u8> {
+    info!("Handling GetHistoryReq: {:?}", req);
+    let resp = get_history_chunk(req, station).unwrap_or_else(|e| {
+        error!("Error reading history: {}", e);
+        msg::ps::GetHistoryResp::new(req.chunk, 0, Vec::new(), e)
     });
-    out.insert(0, MessageId::GetTemperatureResp as u8);
-    out
+
+    codec::encode(&resp)
+}
+
+// notifications are pushed as datagrams, stream transports have no place to put them between responses
+fn udp_subscriber(peer: &Peer) -> Result<SocketAddr, String> {
+    match peer {
+        Peer::Udp(addr) => Ok(*addr),
+        _ => Err(String::from("Subscriptions are only available over UDP")),
+    }
+}
+
+fn handle_subscribe_req(req: &msg::ps::SubscribeReq, station: &Station, peer: &Peer) -> Vec<u8> {
+    info!("Handling SubscribeReq from {}: {:?}", peer, req);
+    let resp = match udp_subscriber(peer).and_then(|addr| station.subscriptions().subscribe(addr, req)) {
+        Ok((subscription_id, lease_ms)) => msg::ps::SubscribeResp::new(subscription_id, lease_ms, String::new()),
+        Err(e) => {
+            error!("Subscribing failed: {}", e);
+            msg::ps::SubscribeResp::new(req.subscription_id, 0, e)
+        }
+    };
+
+    codec::encode(&resp)
+}
+
+fn handle_unsubscribe_req(req: &msg::ps::UnsubscribeReq, station: &Station, peer: &Peer) -> Vec<u8> {
+    info!("Handling UnsubscribeReq from {}: {:?}", peer, req);
+    let resp = match udp_subscriber(peer).and_then(|addr| station.subscriptions().unsubscribe(addr, req.subscription_id)) {
+        Ok(_) => msg::ps::UnsubscribeResp::new(String::new()),
+        Err(e) => {
+            error!("Unsubscribing failed: {}", e);
+            msg::ps::UnsubscribeResp::new(e)
+        }
+    };
+
+    codec::encode(&resp)
+}
+
+fn read_sensors(req: &msg::ps::ReadSensorsReq, station: &Station) -> Result<Vec<msg::ps::SensorReading>, String> {
+    const MAX_SENSORS: usize = 32; // keeps the response within a datagram even with errors
+    if req.sensors.is_empty() || req.sensors.len() > MAX_SENSORS {
+        return Err(format!("Between 1 and {} sensors can be read at once", MAX_SENSORS));
+    }
+    // fresh reads happen back to back, so the values are as close in time as the buses allow
+    let readings = req
+        .sensors
+        .iter()
+        .map(|name| match Sensor::from_name(name) {
+            Some(sensor) => station.read(sensor, req.fresh).to_msg(sensor),
+            None => msg::ps::SensorReading::new(name.clone(), 0, 0, format!("Unknown sensor {}", name)),
+        })
+        .collect();
+    Ok(readings)
+}
+
+fn handle_read_sensors_req(req: &msg::ps::ReadSensorsReq, station: &Station) -> Vec<u8> {
+    info!("Handling ReadSensorsReq: {:?}", req);
+    let resp = match read_sensors(req, station) {
+        Ok(readings) => msg::ps::ReadSensorsResp::new(readings, String::new()),
+        Err(e) => {
+            error!("Error reading sensors: {}", e);
+            msg::ps::ReadSensorsResp::new(Vec::new(), e)
+        }
+    };
+
+    codec::encode(&resp)
+}
+
+fn discovery_info(args: &Args, context: &AppContext) -> msg::ps::DiscoverResp {
+    let mut capabilities = vec![String::from("udp"), String::from("subscriptions"), String::from("read_sensors")];
+    if args.tcp {
+        capabilities.push(String::from("tcp"));
+    }
+    if let Some(http_port) = args.http_port {
+        capabilities.push(format!("http:{}", http_port));
+    }
+    if context.history.is_some() {
+        capabilities.push(String::from("history"));
+    }
+    if context.mqtt.is_some() {
+        capabilities.push(String::from("mqtt"));
+    }
+    if context.register_access {
+        capabilities.push(String::from("register_access"));
+    }
+    msg::ps::DiscoverResp::new(
+        context.identity.name.clone(),
+        context.identity.id.clone(),
+        args.ip.clone(),
+        args.port,
+        msg::PROTOCOL_VERSION,
+        capabilities,
+    )
 }
 
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
-    let mut hw = hw::Hw::new(AppContext::new(args.hw_config));
+    let mut context = AppContext::new(args.hw_config.clone());
+    let discovery = discovery_info(&args, &context);
+    if let Some(trace_path) = args.i2c_trace {
+        context.i2c_trace = I2cTraceMode::Record(trace_path);
+    } else if let Some(trace_path) = args.i2c_replay {
+        context.i2c_trace = I2cTraceMode::Replay(trace_path);
+    }
+    let sampling = context.sampling.clone();
+    let mqtt = context.mqtt.clone();
+    let auth = context.auth.clone();
+    let access = context.access.clone();
+    let history = context
+        .history
+        .as_ref()
+        .map(|config| storage::HistoryStore::open(config).expect("History storage initialization failed"));
+    let hw = hw::Hw::new(context);
     hw.initialize().expect("HW initialization failed");
+    let station = Station::new(hw, history, &sampling, auth, access);
+    sampler::spawn_sampler(sampling, station.shared_hw(), station.cache(), station.history());
+
+    if let Some(mqtt) = mqtt {
+        mqtt::spawn_mqtt_publisher(mqtt, station.clone());
+    }
 
     let addr = SocketAddr::new(args.ip.parse().unwrap(), args.port);
-    let sock = UdpSocket::bind(addr).expect("Failed to bind UDP socket");
+    if args.tcp {
+        transport::spawn_tcp_server(addr, station.clone(), route).expect("Failed to bind TCP socket");
+    }
+    if let Some(http_port) = args.http_port {
+        // the HTTP API has no way to authenticate clients, it would bypass a required key
+        assert!(!station.auth_required(), "The HTTP API can't be enabled while authentication is required");
+        let http_addr = SocketAddr::new(addr.ip(), http_port);
+        http_api::spawn_http_server(http_addr, station.clone()).expect("Failed to start HTTP server");
+    }
+    if let Some(path) = &args.unix_socket {
+        transport::spawn_unix_server(path, args.unix_socket_mode, station.clone(), route)
+            .expect("Failed to bind unix socket");
+    }
+    // a station only reachable through loopback has nothing to announce to the network
+    let discoverable = args.discovery || !(args.no_discovery || addr.ip().is_loopback());
+    if discoverable {
+        discovery::spawn_discovery_responder(args.discovery_port, addr.ip(), discovery, station.clone())
+            .expect("Failed to bind discovery socket");
+    }
+    let sock = Arc::new(UdpSocket::bind(addr).expect("Failed to bind UDP socket"));
+    subscriptions::spawn_notifier(sock.clone(), station.clone());
+    let workers = WorkerPool::new(args.workers, args.worker_queue);
     let mut buf = [0; 1024];
 
     info!("Listening on {}", addr);
@@ -147,8 +452,26 @@ fn main() -> std::io::Result<()> {
         let (len, src_addr) = sock.recv_from(&mut buf)?;
         info!("{:?} bytes received from {:?}", len, src_addr);
         info!("{:?}", &buf[..len]);
+        if len == 0 {
+            continue;
+        }
+        // dropped without an answer, a flood should cost as little as possible
+        if let Err(rejection) = station.access().check(src_addr.ip()) {
+            info!("Dropping request from {}: {:?}", src_addr, rejection);
+            continue;
+        }
 
-        let resp = route(buf[0], &buf[1..len], &mut hw);
-        sock.send_to(resp.as_slice(), src_addr)?;
+        let request = buf[..len].to_vec();
+        let reply_sock = sock.clone();
+        let station = station.clone();
+        let queued = workers.execute(move || {
+            let resp = route(request[0], &request[1..], &station, &Peer::Udp(src_addr));
+            if let Err(e) = reply_sock.send_to(resp.as_slice(), src_addr) {
+                error!("Sending response to {} failed: {}", src_addr, e);
+            }
+        });
+        if !queued {
+            info!("Dropping request from {}: all workers busy", src_addr);
+        }
     }
 }
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use crate::app_context::Sensor;
+use crate::hw::I2cCounters;
+use plantstation_protocol::msg::MessageId;
+use crate::station::Station;
+
+/// Metric name and the counter it exports
+type CounterMetric = (&'static str, fn(&I2cCounters) -> u32);
+
+/// Number of received requests per message id
+#[derive(Default)]
+pub struct RequestCounters {
+    counts: Mutex<BTreeMap<u8, u64>>,
+}
+
+impl RequestCounters {
+    pub fn increment(&self, msg_id: u8) {
+        *self.counts.lock().unwrap().entry(msg_id).or_insert(0) += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<u8, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+fn sensor_metric(sensor: Sensor) -> (&'static str, String) {
+    match sensor {
+        Sensor::Temperature => ("plantstation_temperature_celsius", String::new()),
+        Sensor::Pressure => ("plantstation_pressure_hectopascals", String::new()),
+        Sensor::Humidity(channel) => ("plantstation_humidity_percent", format!("{{channel=\"{}\"}}", channel)),
+        Sensor::AdcRaw(channel) => ("plantstation_adc_raw", format!("{{channel=\"{}\"}}", channel)),
+        Sensor::AdcMillivolts(channel) => ("plantstation_adc_millivolts", format!("{{channel=\"{}\"}}", channel)),
+    }
+}
+
+/// Prometheus text exposition of cached readings and counters, never touches the I2C buses
+pub fn render(station: &Station) -> String {
+    let mut out = String::new();
+    let cache = station.cache();
+    let readings: Vec<_> = station
+        .hw()
+        .sensors()
+        .into_iter()
+        .filter_map(|sensor| cache.get(sensor).map(|reading| (sensor, reading)))
+        .collect();
+
+    let mut values: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (sensor, reading) in readings.iter() {
+        if let Ok(value) = reading.value {
+            let (name, labels) = sensor_metric(*sensor);
+            values.entry(name).or_default().push(format!("{}{} {}", name, labels, value));
+        }
+    }
+    for (name, lines) in values {
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for line in lines {
+            let _ = writeln!(out, "{}", line);
+        }
+    }
+    let _ = writeln!(out, "# TYPE plantstation_sensor_read_duration_seconds gauge");
+    for (sensor, reading) in readings.iter() {
+        let _ = writeln!(
+            out,
+            "plantstation_sensor_read_duration_seconds{{sensor=\"{}\"}} {}",
+            sensor.name(), reading.read_duration.as_secs_f64()
+        );
+    }
+    let _ = writeln!(out, "# TYPE plantstation_reading_age_seconds gauge");
+    for (sensor, reading) in readings.iter() {
+        let _ = writeln!(
+            out,
+            "plantstation_reading_age_seconds{{sensor=\"{}\"}} {}",
+            sensor.name(), reading.age_ms() as f64 / 1000.0
+        );
+    }
+    let _ = writeln!(out, "# TYPE plantstation_sensor_read_ok gauge");
+    for (sensor, reading) in readings.iter() {
+        let _ = writeln!(
+            out,
+            "plantstation_sensor_read_ok{{sensor=\"{}\"}} {}",
+            sensor.name(), reading.value.is_ok() as u8
+        );
+    }
+
+    let counters = station.hw().i2c_counters();
+    let i2c_metrics: [CounterMetric; 3] = [
+        ("plantstation_i2c_errors_total", |c| c.errors.load(Ordering::Relaxed)),
+        ("plantstation_i2c_retries_total", |c| c.retries.load(Ordering::Relaxed)),
+        ("plantstation_i2c_recoveries_total", |c| c.recoveries.load(Ordering::Relaxed)),
+    ];
+    for (name, counter) in i2c_metrics {
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (bus, bus_counters) in counters.iter() {
+            let _ = writeln!(out, "{}{{bus=\"{}\"}} {}", name, bus, counter(bus_counters));
+        }
+    }
+
+    let access = station.access().counters();
+    let _ = writeln!(out, "# TYPE plantstation_requests_rejected_total counter");
+    let _ = writeln!(out, "plantstation_requests_rejected_total{{reason=\"denied\"}} {}", access.denied);
+    let _ = writeln!(out, "plantstation_requests_rejected_total{{reason=\"rate_limited\"}} {}", access.rate_limited);
+
+    // ids without a message name all count as Unknown, one series per label set
+    let mut requests: BTreeMap<&str, u64> = BTreeMap::new();
+    for (msg_id, count) in station.requests().snapshot() {
+        *requests.entry(MessageId::name(msg_id)).or_insert(0) += count;
+    }
+    let _ = writeln!(out, "# TYPE plantstation_requests_total counter");
+    for (message, count) in requests {
+        let _ = writeln!(out, "plantstation_requests_total{{message=\"{}\"}} {}", message, count);
+    }
+    out
+}
@@ -0,0 +1,98 @@
+use std::thread;
+use std::time::Duration;
+use chrono::Utc;
+use json::JsonValue;
+use log::{error, info};
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use crate::app_context::{MqttConfig, Sensor};
+use crate::sampler::Reading;
+use crate::station::Station;
+
+mod home_assistant;
+
+const KEEP_ALIVE_S: u64 = 30;
+const RECONNECT_DELAY_MS: u64 = 5000;
+const REQUEST_QUEUE: usize = 64;
+const ONLINE: &str = "online";
+const OFFLINE: &str = "offline";
+
+/// Topic for availability messages, "offline" is published by the broker as last will
+pub fn availability_topic(config: &MqttConfig) -> String {
+    format!("{}/status", config.topic_prefix)
+}
+
+/// One topic per sensor, channel becomes its own level: "<prefix>/humidity/0"
+pub fn sensor_topic(config: &MqttConfig, sensor: Sensor) -> String {
+    format!("{}/{}", config.topic_prefix, sensor.name().replace(':', "/"))
+}
+
+fn reading_payload(reading: &Reading) -> JsonValue {
+    let mut payload = json::object! {
+        age_ms: reading.age_ms(),
+        timestamp: Utc::now().to_rfc3339(),
+    };
+    match &reading.value {
+        Ok(value) => payload["value"] = (*value).into(),
+        Err(e) => payload["error"] = e.clone().into(),
+    }
+    payload
+}
+
+fn publish(client: &Client, topic: String, payload: String) {
+    if let Err(e) = client.try_publish(topic.as_str(), QoS::AtLeastOnce, true, payload) {
+        error!("Publishing to {} failed: {}", topic, e);
+    }
+}
+
+/// Publishes cached readings of all sensors to the broker on the configured interval
+pub fn spawn_mqtt_publisher(config: MqttConfig, station: Station) {
+    let mut options = MqttOptions::new(config.client_id.clone(), config.host.clone(), config.port);
+    options.set_keep_alive(Duration::from_secs(KEEP_ALIVE_S));
+    options.set_last_will(LastWill::new(availability_topic(&config), OFFLINE, QoS::AtLeastOnce, true));
+    if let Some(username) = &config.username {
+        options.set_credentials(username.clone(), config.password.clone().unwrap_or_default());
+    }
+    let (client, mut connection) = Client::new(options, REQUEST_QUEUE);
+
+    // the connection has to be polled for anything to be sent, it reconnects on its own
+    let status_client = client.clone();
+    let status_config = config.clone();
+    let sensors = station.hw().sensors();
+    let model = station.hw().hardware_model();
+    let broker = format!("{}:{}", config.host, config.port);
+    thread::spawn(move || {
+        for event in connection.iter() {
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}", broker);
+                    if let Some(discovery_prefix) = &status_config.discovery_prefix {
+                        for sensor in sensors.iter() {
+                            publish(
+                                &status_client,
+                                home_assistant::discovery_topic(&status_config, discovery_prefix, *sensor),
+                                home_assistant::discovery_payload(&status_config, &model, *sensor).dump(),
+                            );
+                        }
+                    }
+                    publish(&status_client, availability_topic(&status_config), ONLINE.to_string());
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    error!("MQTT connection to {} failed: {}", broker, e);
+                    thread::sleep(Duration::from_millis(RECONNECT_DELAY_MS));
+                }
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let sensors = station.hw().sensors();
+        loop {
+            for sensor in sensors.iter() {
+                let reading = station.read(*sensor, false);
+                publish(&client, sensor_topic(&config, *sensor), reading_payload(&reading).dump());
+            }
+            thread::sleep(Duration::from_millis(config.interval_ms));
+        }
+    });
+}
@@ -0,0 +1,58 @@
+use json::JsonValue;
+use crate::app_context::{MqttConfig, Sensor};
+use crate::mqtt::{availability_topic, sensor_topic};
+
+/// Device class and unit Home Assistant should show for the sensor
+fn sensor_class(sensor: Sensor) -> (Option<&'static str>, Option<&'static str>) {
+    match sensor {
+        Sensor::Temperature => (Some("temperature"), Some("°C")),
+        Sensor::Pressure => (Some("pressure"), Some("hPa")),
+        Sensor::Humidity(_) => (Some("moisture"), Some("%")),
+        Sensor::AdcMillivolts(_) => (Some("voltage"), Some("mV")),
+        Sensor::AdcRaw(_) => (None, None),
+    }
+}
+
+fn display_name(sensor: Sensor) -> String {
+    match sensor {
+        Sensor::Temperature => String::from("Temperature"),
+        Sensor::Pressure => String::from("Pressure"),
+        Sensor::Humidity(channel) => format!("Humidity {}", channel),
+        Sensor::AdcRaw(channel) => format!("ADC raw {}", channel),
+        Sensor::AdcMillivolts(channel) => format!("ADC voltage {}", channel),
+    }
+}
+
+fn object_id(config: &MqttConfig, sensor: Sensor) -> String {
+    format!("{}_{}", config.client_id, sensor.name().replace(':', "_"))
+}
+
+pub fn discovery_topic(config: &MqttConfig, discovery_prefix: &str, sensor: Sensor) -> String {
+    format!("{}/sensor/{}/{}/config", discovery_prefix, config.client_id, object_id(config, sensor))
+}
+
+/// Discovery config of one sensor, all sensors of the station are grouped under one device
+pub fn discovery_payload(config: &MqttConfig, model: &str, sensor: Sensor) -> JsonValue {
+    let mut payload = json::object! {
+        name: display_name(sensor),
+        unique_id: object_id(config, sensor),
+        state_topic: sensor_topic(config, sensor),
+        value_template: "{{ value_json.value }}",
+        availability_topic: availability_topic(config),
+        state_class: "measurement",
+        device: {
+            identifiers: [config.client_id.clone()],
+            name: config.client_id.clone(),
+            model: model,
+            manufacturer: "PlantStation",
+        },
+    };
+    let (device_class, unit) = sensor_class(sensor);
+    if let Some(device_class) = device_class {
+        payload["device_class"] = device_class.into();
+    }
+    if let Some(unit) = unit {
+        payload["unit_of_measurement"] = unit.into();
+    }
+    payload
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{error, info};
+use plantstation_protocol::msg::ps::SensorReading;
+use crate::app_context::{SamplingConfig, Sensor};
+use crate::hw::Hw;
+use crate::storage::HistoryStore;
+
+#[derive(Clone, Debug)]
+pub struct Reading {
+    pub value: Result<i32, String>,
+    pub taken_at: Instant,
+    pub read_duration: Duration, // time spent on the I2C read
+}
+
+impl Reading {
+    pub fn age_ms(&self) -> u32 {
+        self.taken_at.elapsed().as_millis().min(u32::MAX as u128) as u32
+    }
+
+    pub fn to_msg(&self, sensor: Sensor) -> SensorReading {
+        match &self.value {
+            Ok(value) => SensorReading::new(sensor.name(), *value, self.age_ms(), String::new()),
+            Err(e) => SensorReading::new(sensor.name(), 0, self.age_ms(), e.clone()),
+        }
+    }
+}
+
+/// Last reading of every sampled sensor
+#[derive(Default)]
+pub struct SensorCache {
+    readings: Mutex<HashMap<Sensor, Reading>>,
+}
+
+impl SensorCache {
+    pub fn get(&self, sensor: Sensor) -> Option<Reading> {
+        self.readings.lock().unwrap().get(&sensor).cloned()
+    }
+
+    pub fn update(&self, sensor: Sensor, value: Result<i32, String>, read_duration: Duration) -> Reading {
+        let reading = Reading {
+            value,
+            taken_at: Instant::now(),
+            read_duration,
+        };
+        self.readings.lock().unwrap().insert(sensor, reading.clone());
+        reading
+    }
+}
+
+/// Reads configured sensors on their own intervals and stores results in the cache and history
+pub fn spawn_sampler(
+    sampling: Vec<SamplingConfig>,
+    hw: Arc<Hw>,
+    cache: Arc<SensorCache>,
+    history: Option<Arc<HistoryStore>>,
+) {
+    if sampling.is_empty() {
+        info!("No sensors configured for sampling");
+        return;
+    }
+    thread::spawn(move || {
+        let mut next_due: Vec<Instant> = vec![Instant::now(); sampling.len()];
+        loop {
+            let now = Instant::now();
+            for (config, due) in sampling.iter().zip(next_due.iter_mut()) {
+                if *due > now {
+                    continue;
+                }
+                let started = Instant::now();
+                let value = hw.read_sensor(config.sensor);
+                let read_duration = started.elapsed();
+                match (&value, &history) {
+                    (Ok(sample), Some(history)) => history.append(config.sensor, *sample),
+                    (Err(e), _) => error!("Sampling {:?} failed: {}", config.sensor, e),
+                    _ => (),
+                }
+                cache.update(config.sensor, value, read_duration);
+                *due = now + Duration::from_millis(config.interval_ms);
+            }
+            let earliest = *next_due.iter().min().unwrap();
+            thread::sleep(earliest.saturating_duration_since(Instant::now()));
+        }
+    });
+}
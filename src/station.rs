@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::access::AccessControl;
+use crate::app_context::{AccessConfig, AuthConfig, SamplingConfig, Sensor};
+use crate::hw::Hw;
+use crate::metrics::RequestCounters;
+use plantstation_protocol::msg::auth::Authenticator;
+use crate::sampler::{Reading, SensorCache};
+use crate::storage::HistoryStore;
+use crate::subscriptions::Subscriptions;
+
+/// Shared state of the running station, handed to every request handler
+#[derive(Clone)]
+pub struct Station {
+    hw: Arc<Hw>,
+    cache: Arc<SensorCache>,
+    max_age: Arc<HashMap<Sensor, Duration>>, // only sampled sensors are served from the cache
+    history: Option<Arc<HistoryStore>>,
+    requests: Arc<RequestCounters>,
+    subscriptions: Arc<Subscriptions>,
+    auth: Option<Arc<Authenticator>>,
+    auth_required: bool,
+    access: Arc<AccessControl>,
+}
+
+impl Station {
+    pub fn new(
+        hw: Hw,
+        history: Option<HistoryStore>,
+        sampling: &[SamplingConfig],
+        auth: Option<AuthConfig>,
+        access: AccessConfig,
+    ) -> Station {
+        // one missed sample is tolerated, e.g. while a slow read holds the bus
+        let max_age = sampling
+            .iter()
+            .map(|config| (config.sensor, Duration::from_millis(config.interval_ms.saturating_mul(2))))
+            .collect();
+        Station {
+            hw: Arc::new(hw),
+            cache: Arc::new(SensorCache::default()),
+            max_age: Arc::new(max_age),
+            history: history.map(Arc::new),
+            requests: Arc::new(RequestCounters::default()),
+            subscriptions: Arc::new(Subscriptions::default()),
+            auth_required: auth.as_ref().is_some_and(|auth| auth.required),
+            auth: auth.map(|auth| Arc::new(Authenticator::new(&auth.psk, auth.window_ms))),
+            access: Arc::new(AccessControl::new(access)),
+        }
+    }
+
+    pub fn hw(&self) -> &Hw {
+        &self.hw
+    }
+
+    pub fn shared_hw(&self) -> Arc<Hw> {
+        self.hw.clone()
+    }
+
+    pub fn cache(&self) -> Arc<SensorCache> {
+        self.cache.clone()
+    }
+
+    pub fn history(&self) -> Option<Arc<HistoryStore>> {
+        self.history.clone()
+    }
+
+    pub fn requests(&self) -> &RequestCounters {
+        &self.requests
+    }
+
+    pub fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+
+    /// Set when a pre-shared key is configured, responses and notifications are then signed
+    pub fn authenticator(&self) -> Option<&Authenticator> {
+        self.auth.as_deref()
+    }
+
+    pub fn auth_required(&self) -> bool {
+        self.auth_required
+    }
+
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+
+    /// Sampled sensors are read by the sampler, requests are served from its cache
+    pub fn is_sampled(&self, sensor: Sensor) -> bool {
+        self.max_age.contains_key(&sensor)
+    }
+
+    /// Returns the cached sample of a sampled sensor, or reads the sensor when forced, unsampled or the sample is stale
+    pub fn read(&self, sensor: Sensor, fresh: bool) -> Reading {
+        if !fresh
+            && let Some(max_age) = self.max_age.get(&sensor)
+            && let Some(reading) = self.cache.get(sensor)
+            && reading.taken_at.elapsed() <= *max_age
+        {
+            return reading;
+        }
+        let started = Instant::now();
+        let value = self.hw().read_sensor(sensor);
+        self.cache.update(sensor, value, started.elapsed())
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use log::{error, info};
+use crate::app_context::{HistoryConfig, Sensor};
+
+const MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+const COMPACT_INTERVAL_MS: i64 = 60 * 60 * 1000;
+// chunked GetHistoryReq queries read the aggregate once instead of once per chunk
+const AGGREGATE_CACHE_TTL: Duration = Duration::from_secs(10);
+const AGGREGATE_CACHE_ENTRIES: usize = 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub timestamp_ms: i64, // unix time
+    pub sensor: Sensor,
+    pub value: i32,
+}
+
+impl Sample {
+    fn to_line(self) -> String {
+        format!("{},{},{}", self.timestamp_ms, self.sensor.name(), self.value)
+    }
+
+    fn from_line(line: &str) -> Option<Sample> {
+        let mut fields = line.split(',');
+        let timestamp_ms = fields.next()?.parse().ok()?;
+        let sensor = Sensor::from_name(fields.next()?)?;
+        let value = fields.next()?.parse().ok()?;
+        Some(Sample { timestamp_ms, sensor, value })
+    }
+}
+
+/// Aggregated samples from [start_ms, start_ms + resolution)
+#[derive(Clone, Copy, Debug)]
+pub struct Bucket {
+    pub start_ms: i64,
+    pub min: i32,
+    pub max: i32,
+    pub avg: i32,
+    pub count: u32,
+}
+
+struct HistoryFile {
+    file: File,
+    last_compaction_ms: i64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct AggregateQuery {
+    sensor: Sensor,
+    from_ms: i64,
+    to_ms: i64,
+    resolution_ms: i64,
+}
+
+struct CachedAggregate {
+    query: AggregateQuery,
+    computed_at: Instant,
+    buckets: Arc<Vec<Bucket>>,
+}
+
+/// Append-only csv file with sampled readings, old samples are dropped on compaction
+pub struct HistoryStore {
+    path: String,
+    retention_ms: i64,
+    history_file: Mutex<HistoryFile>, // guards appends and compaction, readers open the path themselves
+    aggregates: Mutex<VecDeque<CachedAggregate>>, // most recent first
+}
+
+fn open_append(path: &str) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl HistoryStore {
+    pub fn open(config: &HistoryConfig) -> Result<HistoryStore, String> {
+        let file = open_append(&config.path)
+            .map_err(|e| format!("Opening history file {} failed: {}", config.path, e))?;
+        let store = HistoryStore {
+            path: config.path.clone(),
+            retention_ms: config.retention_days as i64 * MS_PER_DAY,
+            history_file: Mutex::new(HistoryFile { file, last_compaction_ms: 0 }),
+            aggregates: Mutex::new(VecDeque::new()),
+        };
+        store.compact();
+        Ok(store)
+    }
+
+    pub fn append(&self, sensor: Sensor, value: i32) {
+        let sample = Sample { timestamp_ms: Utc::now().timestamp_millis(), sensor, value };
+        let compaction_due = {
+            let mut history_file = self.history_file.lock().unwrap();
+            if let Err(e) = writeln!(history_file.file, "{}", sample.to_line()) {
+                error!("Writing history sample failed: {}", e);
+            }
+            sample.timestamp_ms - history_file.last_compaction_ms > COMPACT_INTERVAL_MS
+        };
+        if compaction_due {
+            self.compact();
+        }
+    }
+
+    /// Samples of the sensor taken between from_ms and to_ms (unix time, inclusive)
+    pub fn query(&self, sensor: Sensor, from_ms: i64, to_ms: i64) -> Result<Vec<Sample>, String> {
+        // no lock needed: samples are appended as whole lines and compaction replaces the file
+        // with a rename, so the opened file stays complete while it is read
+        let file = File::open(&self.path).map_err(|e| format!("Reading history failed: {}", e))?;
+        let samples = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| Sample::from_line(&line))
+            .filter(|sample| {
+                sample.sensor == sensor && sample.timestamp_ms >= from_ms && sample.timestamp_ms <= to_ms
+            })
+            .collect();
+        Ok(samples)
+    }
+
+    /// Buckets of the query, repeated queries within a few seconds are served from a cache
+    pub fn aggregate(&self, sensor: Sensor, from_ms: i64, to_ms: i64, resolution_ms: i64) -> Result<Arc<Vec<Bucket>>, String> {
+        if resolution_ms <= 0 {
+            return Err(String::from("Resolution must be positive"));
+        }
+        // bucket starts are computed from from_ms, a negative start could overflow them
+        if from_ms < 0 || from_ms > to_ms {
+            return Err(format!("Invalid time range {}..{}", from_ms, to_ms));
+        }
+        let query = AggregateQuery { sensor, from_ms, to_ms, resolution_ms };
+        {
+            let mut aggregates = self.aggregates.lock().unwrap();
+            aggregates.retain(|cached| cached.computed_at.elapsed() < AGGREGATE_CACHE_TTL);
+            if let Some(cached) = aggregates.iter().find(|cached| cached.query == query) {
+                return Ok(cached.buckets.clone());
+            }
+        }
+
+        let buckets = Arc::new(self.compute_aggregate(query)?);
+        let mut aggregates = self.aggregates.lock().unwrap();
+        aggregates.push_front(CachedAggregate { query, computed_at: Instant::now(), buckets: buckets.clone() });
+        aggregates.truncate(AGGREGATE_CACHE_ENTRIES);
+        Ok(buckets)
+    }
+
+    fn compute_aggregate(&self, query: AggregateQuery) -> Result<Vec<Bucket>, String> {
+        let AggregateQuery { sensor, from_ms, to_ms, resolution_ms } = query;
+        let mut buckets: Vec<Bucket> = Vec::new();
+        let mut sum: i64 = 0;
+        for sample in self.query(sensor, from_ms, to_ms)? {
+            let start_ms = from_ms + (sample.timestamp_ms - from_ms) / resolution_ms * resolution_ms;
+            match buckets.last_mut() {
+                Some(bucket) if bucket.start_ms == start_ms => {
+                    bucket.min = bucket.min.min(sample.value);
+                    bucket.max = bucket.max.max(sample.value);
+                    bucket.count += 1;
+                    sum += sample.value as i64;
+                    bucket.avg = (sum / bucket.count as i64) as i32;
+                }
+                _ => {
+                    sum = sample.value as i64;
+                    buckets.push(Bucket {
+                        start_ms,
+                        min: sample.value,
+                        max: sample.value,
+                        avg: sample.value,
+                        count: 1,
+                    });
+                }
+            }
+        }
+        Ok(buckets)
+    }
+
+    /// Rewrites the file without samples older than the retention period
+    fn compact(&self) {
+        let mut history_file = self.history_file.lock().unwrap();
+        let now = Utc::now().timestamp_millis();
+        history_file.last_compaction_ms = now;
+        let oldest = now - self.retention_ms;
+
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Reading history for compaction failed: {}", e);
+                return;
+            }
+        };
+        let kept: Vec<Sample> = BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter_map(|line| Sample::from_line(&line))
+            .filter(|sample| sample.timestamp_ms >= oldest)
+            .collect();
+
+        let tmp_path = format!("{}.tmp", self.path);
+        let written = File::create(&tmp_path).and_then(|tmp| {
+            let mut writer = BufWriter::new(tmp);
+            for sample in kept.iter() {
+                writeln!(writer, "{}", sample.to_line())?;
+            }
+            writer.flush()
+        });
+        let result = written
+            .and_then(|_| fs::rename(&tmp_path, &self.path))
+            .and_then(|_| open_append(&self.path));
+        match result {
+            Ok(file) => {
+                history_file.file = file;
+                info!("History compacted, {} samples kept", kept.len());
+            }
+            Err(e) => error!("History compaction failed: {}", e),
+        }
+    }
+}
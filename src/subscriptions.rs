@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{error, info};
+use crate::app_context::Sensor;
+use plantstation_protocol::msg::codec;
+use plantstation_protocol::msg::ps::{ReadingNotification, SubscribeReq};
+use crate::sampler::Reading;
+use crate::station::Station;
+
+const MAX_SUBSCRIPTIONS: usize = 32;
+const MIN_INTERVAL_MS: u32 = 100;
+const DEFAULT_LEASE_MS: u32 = 60_000;
+const MAX_LEASE_MS: u32 = 600_000;
+// lease of new subscriptions until the subscriber renews them with the id it was sent
+const UNCONFIRMED_LEASE_MS: u32 = 5_000;
+const TICK_MS: u64 = 50;
+
+struct Subscription {
+    subscriber: SocketAddr,
+    sensors: Vec<Sensor>,
+    interval: Duration,
+    on_change_threshold: u32,
+    expires_at: Instant,
+    next_due: Instant,
+    last_sent: HashMap<Sensor, Result<i32, String>>,
+    confirmed: bool, // the subscriber echoed the id, so it receives datagrams at its source address
+}
+
+/// Subscription whose sensors are read outside the entries lock
+struct DueSubscription {
+    id: u32,
+    sensors: Vec<Sensor>,
+    interval: Duration,
+}
+
+impl Subscription {
+    fn changed(&self, sensor: Sensor, value: &Result<i32, String>) -> bool {
+        if self.on_change_threshold == 0 {
+            return true;
+        }
+        match (self.last_sent.get(&sensor), value) {
+            (Some(Ok(last)), Ok(value)) => last.abs_diff(*value) >= self.on_change_threshold,
+            (Some(Err(last)), Err(error)) => last != error,
+            _ => true,
+        }
+    }
+}
+
+fn random_id() -> u32 {
+    let mut bytes = [0u8; 4];
+    getrandom::getrandom(&mut bytes).expect("No random source for subscription ids");
+    u32::from_le_bytes(bytes)
+}
+
+/// Subscribers pushed with readings over UDP until their lease runs out. Ids are random and
+/// nothing is pushed before the subscriber renews with its id, so a spoofed source address
+/// can't make the station send readings to a third party.
+#[derive(Default)]
+pub struct Subscriptions {
+    entries: Mutex<HashMap<u32, Subscription>>,
+}
+
+impl Subscriptions {
+    /// Creates a subscription or renews an existing one, returns its id and granted lease
+    pub fn subscribe(&self, subscriber: SocketAddr, req: &SubscribeReq) -> Result<(u32, u32), String> {
+        let sensors = req
+            .sensors
+            .iter()
+            .map(|name| Sensor::from_name(name).ok_or(format!("Unknown sensor {}", name)))
+            .collect::<Result<Vec<Sensor>, String>>()?;
+        if sensors.is_empty() {
+            return Err(String::from("No sensors to subscribe to"));
+        }
+        let lease_ms = match req.lease_ms {
+            0 => DEFAULT_LEASE_MS,
+            lease_ms => lease_ms.min(MAX_LEASE_MS),
+        };
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let id = match req.subscription_id {
+            0 => {
+                if entries.len() >= MAX_SUBSCRIPTIONS {
+                    return Err(format!("Subscription limit of {} reached", MAX_SUBSCRIPTIONS));
+                }
+                let mut id = random_id();
+                while id == 0 || entries.contains_key(&id) {
+                    id = random_id();
+                }
+                id
+            }
+            id => match entries.get(&id) {
+                Some(existing) if existing.subscriber == subscriber => id,
+                _ => return Err(format!("Subscription {} not found", id)),
+            },
+        };
+        let confirmed = entries.contains_key(&id);
+        let lease_ms = if confirmed { lease_ms } else { UNCONFIRMED_LEASE_MS.min(lease_ms) };
+        let last_sent = entries.remove(&id).map(|existing| existing.last_sent).unwrap_or_default();
+        entries.insert(
+            id,
+            Subscription {
+                subscriber,
+                sensors,
+                interval: Duration::from_millis(req.interval_ms.max(MIN_INTERVAL_MS) as u64),
+                on_change_threshold: req.on_change_threshold,
+                expires_at: now + Duration::from_millis(lease_ms as u64),
+                next_due: now,
+                last_sent,
+                confirmed,
+            },
+        );
+        if confirmed {
+            info!("Subscription {} for {} leased for {} ms", id, subscriber, lease_ms);
+        } else {
+            info!("Subscription {} for {} waits for confirmation", id, subscriber);
+        }
+        Ok((id, lease_ms))
+    }
+
+    pub fn unsubscribe(&self, subscriber: SocketAddr, id: u32) -> Result<(), String> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&id) {
+            Some(existing) if existing.subscriber == subscriber => {
+                entries.remove(&id);
+                info!("Subscription {} for {} removed", id, subscriber);
+                Ok(())
+            }
+            _ => Err(format!("Subscription {} not found", id)),
+        }
+    }
+
+    /// Drops expired subscriptions and collects notifications of the ones that are due
+    fn due_notifications(&self, station: &Station) -> Vec<(SocketAddr, ReadingNotification)> {
+        let due = self.take_due();
+        if due.is_empty() {
+            return Vec::new();
+        }
+
+        // sensors are read without holding the lock, so requests to subscribe are not blocked by the bus
+        let mut readings: HashMap<Sensor, Reading> = HashMap::new();
+        for subscription in due.iter() {
+            for sensor in subscription.sensors.iter() {
+                if !readings.contains_key(sensor) {
+                    readings.insert(*sensor, current_reading(station, *sensor, subscription.interval));
+                }
+            }
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let mut notifications = Vec::new();
+        for DueSubscription { id, sensors, .. } in due {
+            // unsubscribed while the sensors were read
+            let Some(subscription) = entries.get_mut(&id) else {
+                continue;
+            };
+            let mut changed = Vec::new();
+            for sensor in sensors {
+                let reading = &readings[&sensor];
+                if !subscription.changed(sensor, &reading.value) {
+                    continue;
+                }
+                subscription.last_sent.insert(sensor, reading.value.clone());
+                changed.push(reading.to_msg(sensor));
+            }
+            if !changed.is_empty() {
+                notifications.push((subscription.subscriber, ReadingNotification::new(id, changed)));
+            }
+        }
+        notifications
+    }
+
+    fn take_due(&self) -> Vec<DueSubscription> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|id, subscription| {
+            let alive = subscription.expires_at > now;
+            if !alive {
+                info!("Subscription {} for {} expired", id, subscription.subscriber);
+            }
+            alive
+        });
+
+        let mut due = Vec::new();
+        for (id, subscription) in entries.iter_mut() {
+            if !subscription.confirmed || subscription.next_due > now {
+                continue;
+            }
+            subscription.next_due = now + subscription.interval;
+            due.push(DueSubscription {
+                id: *id,
+                sensors: subscription.sensors.clone(),
+                interval: subscription.interval,
+            });
+        }
+        due
+    }
+}
+
+// sampled sensors come from the sampler's cache so subscribers never add bus traffic for them,
+// others are read again once the last reading is older than the interval
+fn current_reading(station: &Station, sensor: Sensor, interval: Duration) -> Reading {
+    if station.is_sampled(sensor) {
+        return station.read(sensor, false);
+    }
+    match station.cache().get(sensor) {
+        Some(reading) if reading.taken_at.elapsed() < interval => reading,
+        _ => station.read(sensor, true),
+    }
+}
+
+/// Sends due notifications from the station's UDP socket, so subscribers see the address they talk to
+pub fn spawn_notifier(socket: Arc<UdpSocket>, station: Station) {
+    thread::spawn(move || loop {
+        for (subscriber, notification) in station.subscriptions().due_notifications(&station) {
+            let mut out = codec::encode(&notification);
+            if let Some(auth) = station.authenticator() {
+                out = auth.seal(&out);
+            }
+            if let Err(e) = socket.send_to(&out, subscriber) {
+                error!("Sending notification to {} failed: {}", subscriber, e);
+            }
+        }
+        thread::sleep(Duration::from_millis(TICK_MS));
+    });
+}
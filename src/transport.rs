@@ -0,0 +1,259 @@
+use std::fmt;
+use std::fs::{self, DirBuilder, Permissions};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{error, info};
+use crate::access::Rejection;
+use plantstation_protocol::msg::framing::{read_frame, write_frame};
+use crate::station::Station;
+
+/// Where a request came from, handlers use it for anything sent back outside of the response
+#[derive(Clone, Debug)]
+pub enum Peer {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Unix(String), // path of the listening socket, clients are unnamed
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Udp(addr) => write!(f, "udp {}", addr),
+            Peer::Tcp(addr) => write!(f, "tcp {}", addr),
+            Peer::Unix(path) => write!(f, "unix {}", path),
+        }
+    }
+}
+
+// per transport, each connection holds a thread. Counted separately so remote clients can't lock
+// out local tools on the unix socket
+const MAX_CONNECTIONS: usize = 16;
+// connections without a complete request for this long are closed, so idle or trickling clients
+// free their slot
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+// a response the client doesn't take within this long closes the connection
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static TCP_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static UNIX_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Slot of an open connection, released when the connection is closed
+struct ConnectionSlot {
+    open: &'static AtomicUsize,
+}
+
+impl ConnectionSlot {
+    fn acquire(open: &'static AtomicUsize) -> Option<ConnectionSlot> {
+        open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| (count < MAX_CONNECTIONS).then_some(count + 1))
+            .ok()
+            .map(|_| ConnectionSlot { open })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Stream socket with adjustable timeouts, TCP or unix
+trait StreamSocket: Read + Write {
+    fn set_read_deadline(&self, timeout: Duration) -> io::Result<()>;
+    fn set_write_deadline(&self, timeout: Duration) -> io::Result<()>;
+}
+
+impl StreamSocket for TcpStream {
+    fn set_read_deadline(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))
+    }
+
+    fn set_write_deadline(&self, timeout: Duration) -> io::Result<()> {
+        self.set_write_timeout(Some(timeout))
+    }
+}
+
+impl StreamSocket for UnixStream {
+    fn set_read_deadline(&self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(Some(timeout))
+    }
+
+    fn set_write_deadline(&self, timeout: Duration) -> io::Result<()> {
+        self.set_write_timeout(Some(timeout))
+    }
+}
+
+/// Socket timeouts apply to each read or write, this bounds a whole frame: every call only gets
+/// the time left until the deadline
+struct Deadline<'a, S: StreamSocket> {
+    stream: &'a mut S,
+    deadline: Instant,
+}
+
+impl<'a, S: StreamSocket> Deadline<'a, S> {
+    fn new(stream: &'a mut S, timeout: Duration) -> Self {
+        Deadline { stream, deadline: Instant::now() + timeout }
+    }
+
+    fn remaining(&self) -> io::Result<Duration> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        Ok(remaining)
+    }
+}
+
+impl<S: StreamSocket> Read for Deadline<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_deadline(self.remaining()?)?;
+        self.stream.read(buf)
+    }
+}
+
+impl<S: StreamSocket> Write for Deadline<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_deadline(self.remaining()?)?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Request dispatcher shared by all transports: message id, payload, station, peer -> response frame
+pub type Router = fn(u8, &[u8], &Station, &Peer) -> Vec<u8>;
+
+/// Serves length-prefixed frames from one stream connection until the peer disconnects or idles
+fn serve_stream(mut stream: impl StreamSocket, peer: Peer, station: Station, router: Router, _slot: ConnectionSlot) {
+    loop {
+        let request = match read_frame(&mut Deadline::new(&mut stream, READ_TIMEOUT)) {
+            Ok(request) => request,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                info!("Closing idle connection from {}", peer);
+                break;
+            }
+            Err(e) => {
+                error!("Reading frame from {} failed: {}", peer, e);
+                break;
+            }
+        };
+        info!("{:?} bytes received from {}", request.len(), peer);
+        if request.is_empty() {
+            continue;
+        }
+        let resp = match &peer {
+            Peer::Tcp(addr) => match station.access().check(addr.ip()) {
+                Ok(_) => router(request[0], &request[1..], &station, &peer),
+                Err(Rejection::Denied) => {
+                    info!("Closing connection from {}, not in allowlist", peer);
+                    break;
+                }
+                // answered with an empty frame, the client would otherwise wait for a response
+                Err(Rejection::RateLimited) => Vec::new(),
+            },
+            _ => router(request[0], &request[1..], &station, &peer),
+        };
+        if let Err(e) = write_frame(&mut Deadline::new(&mut stream, WRITE_TIMEOUT), &resp) {
+            error!("Sending response to {} failed: {}", peer, e);
+            break;
+        }
+    }
+    info!("Connection from {} closed", peer);
+}
+
+pub fn spawn_tcp_server(addr: SocketAddr, station: Station, router: Router) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("Listening for TCP connections on {}", addr);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let addr = match stream.peer_addr() {
+                        Ok(addr) => addr,
+                        Err(e) => {
+                            error!("Reading TCP peer address failed: {}", e);
+                            continue;
+                        }
+                    };
+                    let peer = Peer::Tcp(addr);
+                    // before taking a slot, so sources outside the allowlist can't hold one
+                    if let Err(rejection) = station.access().check(addr.ip()) {
+                        info!("Refusing connection from {}: {:?}", peer, rejection);
+                        continue;
+                    }
+                    let Some(slot) = ConnectionSlot::acquire(&TCP_CONNECTIONS) else {
+                        info!("Refusing connection from {}, {} connections open", peer, MAX_CONNECTIONS);
+                        continue;
+                    };
+                    let station = station.clone();
+                    thread::spawn(move || serve_stream(stream, peer, station, router, slot));
+                }
+                Err(e) => error!("Accepting TCP connection failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Removes a socket left behind by a previous run, anything else at the path is kept
+fn remove_stale_socket(path: &str) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Binds in a directory only the station can enter and moves the socket into place once it has
+/// its mode, so it is never reachable with the looser permissions of the umask
+fn bind_unix_socket(path: &str, mode: u32) -> io::Result<UnixListener> {
+    let private_dir = format!("{}.{}.tmp", path, process::id());
+    DirBuilder::new().mode(0o700).create(&private_dir)?;
+    let private_path = Path::new(&private_dir).join("socket");
+    let bound = UnixListener::bind(&private_path).and_then(|listener| {
+        fs::set_permissions(&private_path, Permissions::from_mode(mode))?;
+        fs::rename(&private_path, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&private_path);
+    fs::remove_dir(&private_dir)?;
+    bound
+}
+
+/// Listens on a unix socket, access is limited by the socket file mode and its directory
+pub fn spawn_unix_server(path: &str, mode: u32, station: Station, router: Router) -> io::Result<()> {
+    remove_stale_socket(path)?;
+    let listener = bind_unix_socket(path, mode)?;
+    info!("Listening on unix socket {} with mode {:o}", path, mode);
+    let path = path.to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let peer = Peer::Unix(path.clone());
+                    let Some(slot) = ConnectionSlot::acquire(&UNIX_CONNECTIONS) else {
+                        info!("Refusing connection on {}, {} connections open", peer, MAX_CONNECTIONS);
+                        continue;
+                    };
+                    let station = station.clone();
+                    thread::spawn(move || serve_stream(stream, peer, station, router, slot));
+                }
+                Err(e) => error!("Accepting unix socket connection failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
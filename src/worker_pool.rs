@@ -0,0 +1,62 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use log::{debug, error};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of threads executing queued jobs, used to handle requests concurrently
+pub struct WorkerPool {
+    sender: SyncSender<Job>,
+}
+
+impl WorkerPool {
+    /// Jobs beyond queue_len waiting for a worker are dropped, so a flood can't grow memory without bound
+    pub fn new(size: usize, queue_len: usize) -> WorkerPool {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_len.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        for id in 0..size.max(1) {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let job = match receiver.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                // a panicking handler must not take its worker down with it
+                if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                    error!("Worker {} job panicked", id);
+                }
+            });
+            debug!("Worker {} started", id);
+        }
+        WorkerPool { sender }
+    }
+
+    /// Queues the job, returns false if it was dropped because the queue is full
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) -> bool {
+        match self.sender.try_send(Box::new(job)) {
+            Ok(_) => true,
+            Err(TrySendError::Full(_)) => false,
+            Err(TrySendError::Disconnected(_)) => {
+                error!("Worker pool stopped, dropping job");
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn worker_survives_panicking_job() {
+        let pool = WorkerPool::new(1, 4);
+        let (sender, receiver) = mpsc::channel();
+        assert!(pool.execute(|| panic!("handler failed")));
+        assert!(pool.execute(move || sender.send(()).unwrap()));
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
@@ -1,18 +1,60 @@
 import matplotlib.pyplot as plt
 import csv
 import argparse
+import os
+import socket
+import sys
+import time
+
+# generated with: cargo run --bin GenerateBindings
+sys.path.insert(0, os.path.join(os.path.dirname(os.path.abspath(__file__)), "bindings", "python"))
+
+
+def fetch_history(station, sensor, hours, resolution_ms):
+    import plantstation as ps
+    from plantstation.protocol import MessageId
+
+    host, port = station.rsplit(":", 1)
+    sock = socket.socket(socket.AF_INET, socket.SOCK_DGRAM)
+    sock.settimeout(2)
+    to_ms = int(time.time() * 1000)
+    from_ms = to_ms - int(hours * 3600 * 1000)
+    values = []
+    chunk, chunks = 0, 1
+    while chunk < chunks:
+        req = ps.GetHistoryReq(sensor=sensor, from_ms=from_ms, to_ms=to_ms, resolution_ms=resolution_ms, chunk=chunk)
+        sock.sendto(bytes([MessageId.GetHistoryReq]) + req.bincode_serialize(), (host, int(port)))
+        data = sock.recv(65536)
+        if len(data) <= 1 or data[0] != MessageId.GetHistoryResp:
+            sys.exit("Error response received")
+        resp = ps.GetHistoryResp.bincode_deserialize(data[1:])
+        if resp.error:
+            sys.exit(resp.error)
+        values += [int(bucket.avg) for bucket in resp.buckets]
+        chunk, chunks = chunk + 1, resp.chunks
+    return values
+
 
 if __name__ == "__main__":
     # argparse.
     parser = argparse.ArgumentParser(prog="PS Plotter")
-    parser.add_argument('filepath')
+    parser.add_argument('filepath', nargs='?')
+    parser.add_argument('--station', help="host:port to fetch history from instead of reading a csv file")
+    parser.add_argument('--sensor', default="humidity:0")
+    parser.add_argument('--hours', type=float, default=24)
+    parser.add_argument('--resolution-ms', type=int, default=60000)
     args = parser.parse_args()
-    humidity=[]
-    with open(args.filepath) as csvfile:
-        filereader = csv.reader(csvfile, delimiter=',')
-        for row in filereader:
-            humidity.append(int(row[1]))
+    if args.station:
+        humidity = fetch_history(args.station, args.sensor, args.hours, args.resolution_ms)
+    elif args.filepath:
+        humidity=[]
+        with open(args.filepath) as csvfile:
+            filereader = csv.reader(csvfile, delimiter=',')
+            for row in filereader:
+                humidity.append(int(row[1]))
+    else:
+        parser.error("either filepath or --station is required")
 
     plt.plot(humidity, '--')
-    plt.ylabel('Humidity')
+    plt.ylabel(args.sensor if args.station else 'Humidity')
     plt.show()